@@ -0,0 +1,377 @@
+// Copyright (C) 2021, Achiefs.
+
+// To manage paths
+use std::path::{Path, PathBuf};
+// To read the user that approves the changes
+use std::env;
+// To manage date and time
+use std::time::{SystemTime, UNIX_EPOCH};
+use time::{Date, Month, PrimitiveDateTime, Time};
+// To handle JSON objects
+use serde_json::{json, Value};
+// To manage unique event identifier
+use uuid::Uuid;
+// To get own process ID
+use std::process;
+// To match targets with patterns
+use glob::Pattern;
+// To accept each file once
+use std::collections::HashSet;
+
+use crate::baseline::Baseline;
+use crate::config;
+use crate::output::Document;
+use crate::metadata::{self, Metadata};
+use crate::verify::{self, Difference};
+
+// ----------------------------------------------------------------------------
+
+// Audit record of accepted changes, stored with the rest of events
+pub struct Approval {
+    pub id: String,
+    pub timestamp: String,
+    pub hostname: String,
+    pub nodename: String,
+    pub version: String,
+    // Approver given in the command line, the real user running it is kept too
+    pub user: String,
+    pub real_user: String,
+    pub real_uid: Option<u32>,
+    pub reason: String,
+    pub targets: Vec<String>,
+    pub since: Option<i64>,
+    pub files: Vec<Difference>,
+    pub pid: u32,
+    pub system: String
+}
+
+impl Approval {
+    pub fn new(config: &config::Config, user: String, reason: String, targets: Vec<String>, since: Option<i64>) -> Self {
+        let (real_uid, real_user) = get_real_user();
+        Approval {
+            id: format!("{}", Uuid::new_v4()),
+            timestamp: format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis()),
+            hostname: gethostname::gethostname().into_string().unwrap(),
+            nodename: config.nodename.clone(),
+            version: String::from(config::VERSION),
+            user,
+            real_user,
+            real_uid,
+            reason,
+            targets,
+            since,
+            files: Vec::new(),
+            pid: process::id(),
+            system: config.system.clone()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Document for Approval {
+    fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.clone(),
+            "hostname": self.hostname.clone(),
+            "node": self.nodename.clone(),
+            "pid": self.pid,
+            "version": self.version.clone(),
+            "kind": "ACCEPT",
+            "user": self.user.clone(),
+            "real_user": self.real_user.clone(),
+            "real_uid": self.real_uid,
+            "reason": self.reason.clone(),
+            "targets": self.targets.clone(),
+            "since": self.since,
+            "accepted": self.files.len(),
+            "files": self.files.iter().map(|file| json!({
+                "file": file.path.clone(),
+                "status": file.status.clone(),
+                "checksum_before": file.before.as_ref().map(|entry| entry.checksum.clone()),
+                "checksum_after": file.after.as_ref().map(|entry| entry.checksum.clone())
+            })).collect::<Vec<serde_json::Value>>(),
+            "system": self.system.clone()
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Path or glob given in the command line to select the changes to accept
+enum Target {
+    Path(PathBuf),
+    Glob(Pattern)
+}
+
+impl Target {
+    fn new(value: &str) -> Result<Self, String> {
+        let path = match Path::new(value).is_absolute() {
+            true => PathBuf::from(value),
+            false => env::current_dir().unwrap().join(value)
+        };
+        match value.contains(['*', '?', '[']) {
+            true => match Pattern::new(path.to_str().unwrap()) {
+                Ok(glob) => Ok(Target::Glob(glob)),
+                Err(e) => Err(format!("Invalid glob '{}': {}", value, e))
+            },
+            false => Ok(Target::Path(path))
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Paths select the file itself and everything inside it
+    fn matches(&self, path: &Path) -> bool {
+        match self {
+            Target::Path(target) => path.starts_with(target),
+            Target::Glob(glob) => glob.matches_path(path)
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Read a time as seconds since epoch, "YYYY-MM-DD" or "YYYY-MM-DD HH:MM[:SS]" in UTC
+pub fn parse_time(value: &str) -> Result<i64, String> {
+    let error = || format!("Invalid time '{}', expected seconds since epoch, 'YYYY-MM-DD' or 'YYYY-MM-DD HH:MM:SS'", value);
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok(seconds);
+    }
+
+    let (date, time) = match value.split_once([' ', 'T']) {
+        Some((date, time)) => (date, time.trim_end_matches('Z')),
+        None => (value, "00:00:00")
+    };
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || time.len() < 2 || time.len() > 3 {
+        return Err(error());
+    }
+    let number = |field: &str| field.parse::<u16>().map_err(|_| error());
+    let month = Month::try_from(number(date[1])? as u8).map_err(|_| error())?;
+    let date = Date::from_calendar_date(number(date[0])? as i32, month, number(date[2])? as u8)
+        .map_err(|_| error())?;
+    let seconds = match time.len() { 3 => number(time[2])?, _ => 0 };
+    let time = Time::from_hms(number(time[0])? as u8, number(time[1])? as u8, seconds as u8)
+        .map_err(|_| error())?;
+    Ok(PrimitiveDateTime::new(date, time).assume_utc().unix_timestamp())
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the user running the command, the original one if it was run with sudo
+pub fn get_current_user() -> String {
+    ["SUDO_USER", "USER", "USERNAME"].iter()
+        .find_map(|name| env::var(name).ok().filter(|user| ! user.is_empty()))
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the real user running the command, it can't be changed like the approver name
+#[cfg(unix)]
+pub fn get_real_user() -> (Option<u32>, String) {
+    let uid = uzers::get_current_uid();
+    (Some(uid), metadata::get_user_name(uid))
+}
+
+#[cfg(not(unix))]
+pub fn get_real_user() -> (Option<u32>, String) {
+    (None, env::var("USERNAME").unwrap_or_else(|_| String::from("UNKNOWN")))
+}
+
+// ----------------------------------------------------------------------------
+
+// Check if a change happened after the given time, removed files use the time of their folder
+fn changed_since(difference: &Difference, since: i64) -> bool {
+    let mtime = match &difference.after {
+        Some(entry) => Some(entry.mtime),
+        None => Path::new(&difference.path).parent()
+            .and_then(Metadata::new).map(|metadata| metadata.mtime)
+    };
+    match mtime {
+        Some(mtime) => mtime >= since,
+        None => false
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Store in the approved baseline the current state of the changed files that match
+// any target or were changed after the given time, returning the accepted changes
+pub fn accept(config: &config::Config, approved: &mut Baseline, targets: &[String], since: Option<i64>) -> Result<Vec<Difference>, String> {
+    let targets = targets.iter().map(|target| Target::new(target)).collect::<Result<Vec<Target>, String>>()?;
+    let roots = config.get_roots(&[])?;
+    let report = verify::verify(config, approved, &roots);
+
+    // Each file is accepted once even if several targets select it
+    let mut paths = HashSet::new();
+    let accepted: Vec<Difference> = report.differences.into_iter().filter(|difference| {
+        targets.iter().any(|target| target.matches(Path::new(&difference.path))) ||
+            since.is_some_and(|since| changed_since(difference, since))
+    }).filter(|difference| paths.insert(difference.path.clone())).collect();
+
+    for difference in &accepted {
+        match &difference.after {
+            Some(entry) => { approved.entries.insert(difference.path.clone(), entry.clone()); },
+            None => { approved.entries.remove(&difference.path); }
+        };
+        approved.changed = true;
+    }
+    Ok(accepted)
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event;
+    use std::fs;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_config(root: &str) -> config::Config {
+        let mut config = config::Config::new(env::consts::OS);
+        let yaml = format!("- path: {}", env::current_dir().unwrap().join(root).to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        config
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_accept() {
+        let root = "test_accept";
+        fs::create_dir_all(format!("{}/etc", root)).unwrap();
+        fs::write(format!("{}/etc/file1", root), "This is a test!").unwrap();
+        fs::write(format!("{}/file2", root), "Another test!").unwrap();
+        fs::write(format!("{}/file3.conf", root), "Last test!").unwrap();
+        let config = create_test_config(root);
+        let mut approved = Baseline::new();
+        approved.scan(&config, 0);
+
+        fs::write(format!("{}/etc/file1", root), "Changed content").unwrap();
+        fs::remove_file(format!("{}/file2", root)).unwrap();
+        fs::write(format!("{}/file3.conf", root), "Changed too").unwrap();
+        fs::write(format!("{}/file4.conf", root), "New file").unwrap();
+
+        let accepted = accept(&config, &mut approved, &[String::from("test_accept/etc")], None).unwrap();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].status, "modified");
+        assert!(approved.changed);
+        let roots = config.get_roots(&[]).unwrap();
+        assert_eq!(verify::verify(&config, &approved, &roots).differences.len(), 3);
+
+        let accepted = accept(&config, &mut approved, &[String::from("test_accept/*.conf")], None).unwrap();
+        let mut statuses: Vec<&str> = accepted.iter().map(|difference| difference.status.as_str()).collect();
+        statuses.sort();
+        assert_eq!(statuses, vec!["added", "modified"]);
+        assert_eq!(verify::verify(&config, &approved, &roots).differences.len(), 1);
+
+        // Changes older than the given time are kept
+        let future = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64 + 3600;
+        assert!(accept(&config, &mut approved, &[], Some(future)).unwrap().is_empty());
+        let accepted = accept(&config, &mut approved, &[], Some(0)).unwrap();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].status, "missing");
+        assert!(verify::verify(&config, &approved, &roots).passed());
+        assert_eq!(approved.entries.len(), 3);
+
+        assert!(accept(&config, &mut approved, &[String::from("[")], None).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1654041600"), Ok(1654041600));
+        assert_eq!(parse_time("2022-06-01"), Ok(1654041600));
+        assert_eq!(parse_time("2022-06-01 10:30"), Ok(1654079400));
+        assert_eq!(parse_time("2022-06-01T10:30:15Z"), Ok(1654079415));
+        assert!(parse_time("2022-13-01").is_err());
+        assert!(parse_time("2022-06-01 25:00").is_err());
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("2022-06").is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_approval() {
+        let config = create_test_config("test_approval");
+        let mut approval = Approval::new(&config, String::from("admin"), String::from("Package upgrade"),
+            vec![String::from("/etc")], Some(1654041600));
+        approval.files.push(Difference {
+            path: String::from("/etc/file"), status: String::from("missing"), changes: Vec::new(), before: None, after: None
+        });
+        let json: serde_json::Value = serde_json::from_str(&approval.format_json()).unwrap();
+        assert_eq!(json["kind"], "ACCEPT");
+        assert_eq!(json["id"], approval.id);
+        assert_eq!(json["user"], "admin");
+        assert_eq!(json["reason"], "Package upgrade");
+        assert_eq!(json["since"], 1654041600);
+        assert_eq!(json["accepted"], 1);
+        assert_eq!(json["files"][0]["status"], "missing");
+
+        assert_eq!(json["real_user"], approval.real_user);
+        assert_eq!(json["real_uid"], serde_json::json!(approval.real_uid));
+
+        let filename = String::from("test_approval.json");
+        event::log_json(filename.clone(), approval.format_json()).unwrap();
+        assert_eq!(fs::read_to_string(&filename).unwrap(), format!("{}\n", approval.format_json()));
+        fs::remove_file(filename).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_current_user() {
+        assert!(!get_current_user().is_empty());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_real_user() {
+        let (uid, user) = get_real_user();
+        assert_eq!(uid, Some(uzers::get_current_uid()));
+        assert_eq!(user, metadata::get_user_name(uid.unwrap()));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[cfg(unix)]
+    #[test]
+    fn test_accept_once() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = "test_accept_once";
+        fs::create_dir_all(root).unwrap();
+        let file = format!("{}/file1", root);
+        fs::write(&file, "This is a test!").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        let config = create_test_config(root);
+        let mut approved = Baseline::new();
+        approved.scan(&config, 0);
+
+        // Content and mode changed, selected by two targets
+        fs::write(&file, "Changed content").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        let targets = [String::from(root), format!("{}/file*", root)];
+        let accepted = accept(&config, &mut approved, &targets, Some(0)).unwrap();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].changes, vec!["content", "attributes"]);
+        let roots = config.get_roots(&[]).unwrap();
+        assert!(verify::verify(&config, &approved, &roots).passed());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (C) 2021, Achiefs.
+
+// To wait between attempts
+use std::time::Duration;
+use tokio::time::sleep;
+use std::future::Future;
+// To calculate exponential delays with random jitter
+use retry::delay::{Exponential, jitter};
+// To manage HTTP requests
+use reqwest::{Response, StatusCode};
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::lifecycle;
+
+// How failed requests to the endpoint are sent again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Policy {
+    pub max_attempts: u32,
+    // Milliseconds before the first retry, doubled on each one
+    pub base_delay: u64,
+    pub jitter: bool,
+    // Response statuses worth retrying, connection errors are always retried
+    pub statuses: Vec<u16>
+}
+
+impl Policy {
+    // Retry settings of the output, global values are used if not set
+    pub fn new(config: &config::Config, output: &config::Output) -> Self {
+        Policy {
+            max_attempts: output.retry.max_attempts.unwrap_or(config.retry_max_attempts),
+            base_delay: output.retry.base_delay.unwrap_or(config.retry_base_delay),
+            jitter: output.retry.jitter.unwrap_or(config.retry_jitter),
+            statuses: output.retry.statuses.clone().unwrap_or_else(|| config.retry_statuses.clone())
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn is_retryable(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the waits before each retry
+    pub fn get_delays(&self) -> impl Iterator<Item = Duration> {
+        let random = self.jitter;
+        Exponential::from_millis_with_factor(self.base_delay, 2.0)
+            .map(move |delay| if random { jitter(delay) } else { delay })
+            .take(self.max_attempts.saturating_sub(1) as usize)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Send a request until it gets a response not worth retrying or the attempts run out,
+// the result of the last attempt is returned
+pub async fn send<F, R>(policy: &Policy, name: &str, mut request: F) -> reqwest::Result<Response>
+    where F: FnMut() -> R, R: Future<Output = reqwest::Result<Response>> {
+    let mut delays = policy.get_delays();
+    loop {
+        let result = request().await;
+        let error = match &result {
+            Ok(response) if policy.is_retryable(response.status()) => format!("HTTP status {}", response.status()),
+            Ok(_) => return result,
+            Err(e) => e.to_string()
+        };
+        match delays.next() {
+            Some(delay) => {
+                lifecycle::STATS.add_retry();
+                debug!("{} failed, retrying in {:?}, error: {}", name, delay, error);
+                sleep(delay).await;
+            },
+            None => {
+                lifecycle::STATS.add_failure();
+                if policy.max_attempts > 1 {
+                    debug!("{} failed after {} attempts, error: {}", name, policy.max_attempts, error);
+                }
+                return result;
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::start_server;
+    use reqwest::Client;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_policy(max_attempts: u32) -> Policy {
+        Policy { max_attempts, base_delay: 10, jitter: false, statuses: vec![429, 503] }
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_delays() {
+        let delays: Vec<Duration> = create_test_policy(4).get_delays().collect();
+        assert_eq!(delays, vec![Duration::from_millis(10), Duration::from_millis(20), Duration::from_millis(40)]);
+        assert_eq!(create_test_policy(1).get_delays().count(), 0);
+
+        let policy = Policy { jitter: true, ..create_test_policy(4) };
+        for (delay, limit) in policy.get_delays().zip([10, 20, 40]) {
+            assert!(delay <= Duration::from_millis(limit));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_new() {
+        let config = config::Config::new(std::env::consts::OS);
+        let mut output = config::Output::new("network", "elasticsearch");
+        let policy = Policy::new(&config, &output);
+        assert_eq!(policy.max_attempts, 5);
+        assert_eq!(policy.base_delay, 500);
+        assert!(policy.jitter);
+        assert!(policy.is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(policy.is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.is_retryable(StatusCode::BAD_REQUEST));
+
+        // Settings of the output replace the global ones
+        output.retry.max_attempts = Some(2);
+        output.retry.statuses = Some(vec![400]);
+        let policy = Policy::new(&config, &output);
+        assert_eq!(policy.max_attempts, 2);
+        assert_eq!(policy.base_delay, 500);
+        assert!(policy.is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!policy.is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send() {
+        let client = Client::new();
+        let (address, server) = start_server(vec![503, 429, 201, 503, 503, 400]);
+
+        // Retryable statuses are sent again until a final response arrives
+        let response = send(&create_test_policy(5), "Test request", || client.get(&address).send()).await;
+        assert_eq!(response.unwrap().status(), StatusCode::CREATED);
+        // The last response is returned when attempts run out
+        let response = send(&create_test_policy(2), "Test request", || client.get(&address).send()).await;
+        assert_eq!(response.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+        // Other statuses are not retried
+        let response = send(&create_test_policy(5), "Test request", || client.get(&address).send()).await;
+        assert_eq!(response.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(server.join().unwrap().len(), 6);
+
+        // Connection errors are retried too
+        let response = send(&create_test_policy(2), "Test request", || client.get(&address).send()).await;
+        assert!(response.is_err());
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle files and folders
+use std::fs;
+// To manage paths
+use std::path::{Path, PathBuf};
+// To store baseline entries sorted by path
+use std::collections::{BTreeMap, HashSet};
+// To walk over monitored folders
+use walkdir::WalkDir;
+// To serialize and deserialize baseline entries
+use serde::{Serialize, Deserialize};
+// Event handling
+use notify::op::Op;
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::hash;
+use crate::metadata::Metadata;
+
+// Stored state of a single monitored file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub checksum: String
+}
+
+impl Entry {
+    // Read file metadata and build the entry, None if the file can't be reached
+    pub fn new(path: &Path, checksum: String) -> Option<Self> {
+        Metadata::new(path).map(|metadata| Entry {
+            path: String::from(path.to_str().unwrap()),
+            size: metadata.size,
+            mode: metadata.mode,
+            uid: metadata.uid,
+            gid: metadata.gid,
+            mtime: metadata.mtime,
+            checksum
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the list of operations that transform this entry into the given one
+    pub fn compare(&self, current: &Entry) -> Vec<Op> {
+        let mut operations = Vec::new();
+        if self.checksum != current.checksum || self.size != current.size || self.mtime != current.mtime {
+            operations.push(Op::WRITE);
+        }
+        if self.mode != current.mode || self.uid != current.uid || self.gid != current.gid {
+            operations.push(Op::CHMOD);
+        }
+        operations
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct Baseline {
+    pub entries: BTreeMap<String, Entry>,
+    pub changed: bool
+}
+
+impl Baseline {
+
+    pub fn new() -> Self {
+        Baseline {
+            entries: BTreeMap::new(),
+            changed: false
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Load stored baseline, an empty one is returned if it doesn't exist or is corrupted
+    pub fn load(file: String) -> Self {
+        match fs::read_to_string(file.clone()) {
+            Ok(data) => match serde_json::from_str::<Vec<Entry>>(&data) {
+                Ok(list) => Baseline {
+                    entries: list.into_iter().map(|entry| (entry.path.clone(), entry)).collect(),
+                    changed: false
+                },
+                Err(e) => {
+                    error!("Unable to parse baseline file '{}', error: {}", file, e);
+                    Baseline::new()
+                }
+            },
+            Err(e) => {
+                debug!("Baseline file '{}' not loaded, error: {:?}", file, e.kind());
+                Baseline::new()
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Store baseline, written in a temporary file first to not corrupt it on failure
+    pub fn save(&mut self, file: String) {
+        let tmp_file = format!("{}.tmp", file);
+        let entries: Vec<&Entry> = self.entries.values().collect();
+        match fs::write(tmp_file.clone(), serde_json::to_string(&entries).unwrap())
+            .and_then(|_| fs::rename(tmp_file, file.clone())) {
+            Ok(_) => {
+                debug!("Baseline stored in '{}'", file);
+                self.changed = false
+            },
+            Err(e) => error!("Unable to store baseline in '{}', error: {:?}", file, e)
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Set current state of a file after a detected event
+    pub fn update(&mut self, path: &Path, checksum: String) {
+        let key = String::from(path.to_str().unwrap());
+        match Entry::new(path, checksum) {
+            Some(entry) => { self.entries.insert(key, entry); },
+            None => { self.entries.remove(&key); }
+        };
+        self.changed = true;
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Forget a file that is no longer in its path
+    pub fn remove(&mut self, path: &Path) {
+        if self.entries.remove(path.to_str().unwrap()).is_some() {
+            self.changed = true;
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the number of stored entries of the given monitor entry inside root
+    pub fn count(&self, config: &config::Config, index: usize, root: &Path) -> usize {
+        self.entries.keys().filter(|key| {
+            Path::new(key).starts_with(root) && config.get_index(Path::new(key)) == Some(index)
+        }).count()
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Walk over a monitor path, refresh its entries and return detected changes
+    // with the previous stored entry of each file
+    pub fn scan(&mut self, config: &config::Config, index: usize) -> Vec<(Op, String, Option<Entry>)> {
+        let root = PathBuf::from(&config.monitor[index].path);
+        self.scan_path(config, index, &root)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Same as scan but limited to the files of the monitor entry inside root
+    pub fn scan_path(&mut self, config: &config::Config, index: usize, root: &Path) -> Vec<(Op, String, Option<Entry>)> {
+        let mut changes = Vec::new();
+        let mut found = HashSet::new();
+
+        // Nested monitor paths are skipped, they are scanned with their own entry
+        let walker = WalkDir::new(root).into_iter()
+            .filter_entry(|item| item.depth() == 0 || config.get_index(item.path()) == Some(index));
+        for item in walker.filter_map(|item| item.ok()) {
+            let path = item.path();
+            if item.file_type().is_dir() || config.match_ignore(index, path) { continue; }
+
+            let key = String::from(path.to_str().unwrap());
+            let checksum = hash::get_checksum(key.clone(), config.max_file_size);
+            if let Some(current) = Entry::new(path, checksum) {
+                match self.entries.get(&key) {
+                    Some(stored) => {
+                        for operation in stored.compare(&current) {
+                            changes.push((operation, key.clone(), Some(stored.clone())));
+                        }
+                    },
+                    None => changes.push((Op::CREATE, key.clone(), None))
+                }
+                self.entries.insert(key.clone(), current);
+                found.insert(key);
+            }
+        }
+
+        // Stored entries of this monitor entry that were not found anymore
+        let removed: Vec<String> = self.entries.keys()
+            .filter(|key| ! found.contains(*key) && Path::new(key).starts_with(root) &&
+                config.get_index(Path::new(key)) == Some(index))
+            .cloned().collect();
+        // Entries ignored since they were stored are dropped without reporting them
+        for key in removed {
+            let stored = self.entries.remove(&key);
+            match config.match_ignore(index, Path::new(&key)) {
+                true => self.changed = true,
+                false => changes.push((Op::REMOVE, key, stored))
+            }
+        }
+
+        if ! changes.is_empty() { self.changed = true; }
+        changes
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_config(root: &str) -> config::Config {
+        let mut config = config::Config::new(std::env::consts::OS);
+        let yaml = format!("- path: {}\n  ignore: [\".swp\"]", root);
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        config
+    }
+
+    fn create_test_dir(root: &str) {
+        fs::create_dir_all(format!("{}/subdir", root)).unwrap();
+        fs::write(format!("{}/file1", root), "This is a test!").unwrap();
+        fs::write(format!("{}/subdir/file2", root), "Another test!").unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_entry_new() {
+        let filename = "test_entry_new";
+        fs::write(filename, "This is a test!").unwrap();
+        let entry = Entry::new(Path::new(filename), String::from("CHECKSUM")).unwrap();
+        assert_eq!(entry.path, String::from(filename));
+        assert_eq!(entry.size, 15);
+        assert_eq!(entry.checksum, String::from("CHECKSUM"));
+        assert!(entry.mtime > 0);
+        fs::remove_file(filename).unwrap();
+
+        assert_eq!(Entry::new(Path::new("not_exists"), String::from("UNKNOWN")), None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_entry_compare() {
+        let entry = Entry {
+            path: String::from("test"), size: 10, mode: 0o644, uid: 0, gid: 0,
+            mtime: 1000, checksum: String::from("A")
+        };
+        assert!(entry.compare(&entry.clone()).is_empty());
+
+        let mut written = entry.clone();
+        written.checksum = String::from("B");
+        assert_eq!(entry.compare(&written), vec![Op::WRITE]);
+
+        let mut chmod = entry.clone();
+        chmod.mode = 0o600;
+        assert_eq!(entry.compare(&chmod), vec![Op::CHMOD]);
+
+        let mut both = written.clone();
+        both.uid = 1000;
+        assert_eq!(entry.compare(&both), vec![Op::WRITE, Op::CHMOD]);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_save_load() {
+        let filename = String::from("test_save_load.json");
+        let mut baseline = Baseline::new();
+        baseline.update(Path::new("Cargo.toml"), String::from("CHECKSUM"));
+        assert!(baseline.changed);
+        baseline.save(filename.clone());
+        assert!(!baseline.changed);
+
+        let loaded = Baseline::load(filename.clone());
+        assert_eq!(loaded.entries, baseline.entries);
+        fs::remove_file(filename).unwrap();
+
+        assert!(Baseline::load(String::from("not_exists")).entries.is_empty());
+        assert!(Baseline::load(String::from("README.md")).entries.is_empty());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_update() {
+        let mut baseline = Baseline::new();
+        baseline.update(Path::new("Cargo.toml"), String::from("CHECKSUM"));
+        assert_eq!(baseline.entries["Cargo.toml"].checksum, String::from("CHECKSUM"));
+        baseline.update(Path::new("not_exists"), String::from("UNKNOWN"));
+        assert_eq!(baseline.entries.len(), 1);
+        baseline.entries.insert(String::from("not_exists"), baseline.entries["Cargo.toml"].clone());
+        baseline.update(Path::new("not_exists"), String::from("UNKNOWN"));
+        assert_eq!(baseline.entries.len(), 1);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_remove() {
+        let mut baseline = Baseline::new();
+        baseline.update(Path::new("Cargo.toml"), String::from("CHECKSUM"));
+        baseline.changed = false;
+        baseline.remove(Path::new("not_exists"));
+        assert!(!baseline.changed);
+        baseline.remove(Path::new("Cargo.toml"));
+        assert!(baseline.entries.is_empty());
+        assert!(baseline.changed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scan() {
+        let root = "./test_baseline_scan";
+        create_test_dir(root);
+        let config = create_test_config(root);
+        let mut baseline = Baseline::new();
+
+        let changes = baseline.scan(&config, 0);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|(op, _, previous)| *op == Op::CREATE && previous.is_none()));
+        assert!(baseline.scan(&config, 0).is_empty());
+
+        fs::write(format!("{}/file1", root), "Changed content").unwrap();
+        fs::remove_file(format!("{}/subdir/file2", root)).unwrap();
+        fs::write(format!("{}/file3", root), "New file").unwrap();
+        fs::write(format!("{}/file3.swp", root), "Ignored file").unwrap();
+        let mut changes = baseline.scan(&config, 0);
+        changes.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(changes[0].2.as_ref().unwrap().size, 15);
+        assert!(changes[1].2.is_none());
+        assert_eq!(changes[2].2.as_ref().unwrap().size, 13);
+        let changes: Vec<(Op, String)> = changes.into_iter().map(|(op, path, _)| (op, path)).collect();
+        assert_eq!(changes, vec![
+            (Op::WRITE, PathBuf::from(root).join("file1").to_str().unwrap().to_string()),
+            (Op::CREATE, PathBuf::from(root).join("file3").to_str().unwrap().to_string()),
+            (Op::REMOVE, PathBuf::from(root).join("subdir/file2").to_str().unwrap().to_string())
+        ]);
+        assert_eq!(baseline.entries.len(), 2);
+        assert_eq!(baseline.count(&config, 0, Path::new(root)), 2);
+
+        // Stored files ignored by a new rule are dropped without a change
+        let config = create_test_config(&format!("{}\n  include: [\"file1\"]", root));
+        baseline.changed = false;
+        assert!(baseline.scan(&config, 0).is_empty());
+        assert_eq!(baseline.entries.len(), 1);
+        assert!(baseline.changed);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scan_path() {
+        let root = "./test_baseline_scan_path";
+        create_test_dir(root);
+        let config = create_test_config(root);
+        let mut baseline = Baseline::new();
+        let subdir = PathBuf::from(root).join("subdir");
+
+        let changes = baseline.scan_path(&config, 0, &subdir);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].1, subdir.join("file2").to_str().unwrap());
+        assert_eq!(baseline.count(&config, 0, &subdir), 1);
+        assert_eq!(baseline.count(&config, 0, Path::new(root)), 1);
+
+        // Files out of the scanned path are not reported as removed
+        assert_eq!(baseline.scan(&config, 0).len(), 1);
+        fs::remove_file(format!("{}/file1", root)).unwrap();
+        assert!(baseline.scan_path(&config, 0, &subdir).is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scan_nested() {
+        let root = "./test_baseline_scan_nested";
+        create_test_dir(root);
+        let mut config = config::Config::new(std::env::consts::OS);
+        let yaml = format!("- path: {}\n- path: {}/subdir", root, root);
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let mut baseline = Baseline::new();
+
+        let changes = baseline.scan(&config, 0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].1, PathBuf::from(root).join("file1").to_str().unwrap());
+        assert_eq!(baseline.scan(&config, 1).len(), 1);
+        assert_eq!(baseline.count(&config, 0, Path::new(root)), 1);
+        assert_eq!(baseline.count(&config, 1, Path::new(root)), 1);
+
+        // Entries of the nested path are not removed by the parent scan
+        fs::remove_file(format!("{}/file1", root)).unwrap();
+        let changes = baseline.scan(&config, 0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, Op::REMOVE);
+        assert_eq!(baseline.count(&config, 1, Path::new(root)), 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
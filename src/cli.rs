@@ -0,0 +1,164 @@
+// Copyright (C) 2021, Achiefs.
+
+// To parse command line arguments
+use clap::{Parser, Subcommand};
+
+use crate::config;
+use crate::hash;
+
+// ----------------------------------------------------------------------------
+
+#[derive(Parser, Debug)]
+#[command(name = "fim", version = config::VERSION, about = "Achiefs File Integrity Monitoring")]
+pub struct Cli {
+    #[arg(short, long, global = true, value_name = "PATH",
+        help = "Configuration file, searched in the default locations if not set")]
+    pub config: Option<String>,
+
+    #[arg(long, global = true, value_name = "LEVEL",
+        value_parser = ["debug", "info", "warning", "error"],
+        help = "Override the log level of the configuration file")]
+    pub log_level: Option<String>,
+
+    #[arg(short, long, global = true, help = "Print log messages in the terminal too")]
+    pub foreground: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    #[command(about = "Monitor configured paths, default when no command is given")]
+    Run,
+
+    #[command(about = "Validate the configuration file and exit")]
+    CheckConfig,
+
+    #[command(about = "Scan monitored files once reporting changes against the baseline")]
+    Scan {
+        #[arg(help = "Path inside a monitored path to scan, all monitored paths if not set")]
+        path: Option<String>
+    },
+
+    #[command(about = "Compare monitored files with their approved state, exit code is 1 on differences")]
+    Verify {
+        #[arg(help = "Paths inside monitored paths to verify, all monitored paths if not set")]
+        paths: Vec<String>,
+
+        #[arg(long, help = "Print the report in JSON format")]
+        json: bool,
+
+        #[arg(long, value_name = "FILE", help = "Export the report in JSON format to a file")]
+        report: Option<String>
+    },
+
+    #[command(about = "Store the current state of changed files as approved, recording an audit event")]
+    Accept {
+        #[arg(required_unless_present = "all_since",
+            help = "Paths or globs of the changes to accept, folders include their files")]
+        targets: Vec<String>,
+
+        #[arg(long, value_name = "TIME",
+            help = "Accept all changes done since TIME, seconds since epoch or 'YYYY-MM-DD [HH:MM:SS]' in UTC")]
+        all_since: Option<String>,
+
+        #[arg(short = 'm', long, help = "Reason of the approval stored in the audit event")]
+        reason: String,
+
+        #[arg(long, help = "User that approves the changes, the one running the command if not set")]
+        user: Option<String>
+    },
+
+    #[command(about = "Print the checksums of a file")]
+    Hash {
+        #[arg(help = "File to hash")]
+        file: String,
+
+        #[arg(short, long = "algorithm", value_name = "ALGORITHM",
+            value_parser = hash::ALGORITHMS, default_value = hash::DEFAULT_ALGORITHM,
+            help = "Hash algorithm to use, could be repeated")]
+        algorithms: Vec<String>
+    },
+
+    #[command(about = "Print the version and exit")]
+    Version
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::parse_from(["fim"]);
+        assert_eq!(cli.command, None);
+        assert_eq!(cli.config, None);
+        assert!(!cli.foreground);
+
+        let cli = Cli::parse_from(["fim", "run", "--config", "test.yml", "--foreground", "--log-level", "debug"]);
+        assert_eq!(cli.command, Some(Command::Run));
+        assert_eq!(cli.config, Some(String::from("test.yml")));
+        assert_eq!(cli.log_level, Some(String::from("debug")));
+        assert!(cli.foreground);
+
+        let cli = Cli::parse_from(["fim", "-c", "test.yml", "scan", "/etc"]);
+        assert_eq!(cli.command, Some(Command::Scan { path: Some(String::from("/etc")) }));
+        assert_eq!(cli.config, Some(String::from("test.yml")));
+
+        let cli = Cli::parse_from(["fim", "verify", "/etc", "/bin", "--json", "--report", "report.json"]);
+        assert_eq!(cli.command, Some(Command::Verify {
+            paths: vec![String::from("/etc"), String::from("/bin")], json: true, report: Some(String::from("report.json"))
+        }));
+        assert_eq!(Cli::parse_from(["fim", "verify"]).command, Some(Command::Verify {
+            paths: Vec::new(), json: false, report: None
+        }));
+
+        let cli = Cli::parse_from(["fim", "accept", "/etc/hosts", "/bin/*", "-m", "Package upgrade"]);
+        assert_eq!(cli.command, Some(Command::Accept {
+            targets: vec![String::from("/etc/hosts"), String::from("/bin/*")], all_since: None,
+            reason: String::from("Package upgrade"), user: None
+        }));
+        let cli = Cli::parse_from(["fim", "accept", "--all-since", "2022-06-01", "--reason", "Deploy", "--user", "admin"]);
+        assert_eq!(cli.command, Some(Command::Accept {
+            targets: Vec::new(), all_since: Some(String::from("2022-06-01")),
+            reason: String::from("Deploy"), user: Some(String::from("admin"))
+        }));
+
+        assert_eq!(Cli::parse_from(["fim", "check-config"]).command, Some(Command::CheckConfig));
+        assert_eq!(Cli::parse_from(["fim", "version"]).command, Some(Command::Version));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse_hash() {
+        let cli = Cli::parse_from(["fim", "hash", "file"]);
+        assert_eq!(cli.command, Some(Command::Hash {
+            file: String::from("file"), algorithms: vec![String::from(hash::DEFAULT_ALGORITHM)]
+        }));
+
+        let cli = Cli::parse_from(["fim", "hash", "file", "-a", "md5", "--algorithm", "sha256"]);
+        assert_eq!(cli.command, Some(Command::Hash {
+            file: String::from("file"), algorithms: vec![String::from("md5"), String::from("sha256")]
+        }));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Cli::try_parse_from(["fim", "hash", "file", "-a", "crc32"]).is_err());
+        assert!(Cli::try_parse_from(["fim", "--log-level", "verbose"]).is_err());
+        assert!(Cli::try_parse_from(["fim", "unknown"]).is_err());
+        assert!(Cli::try_parse_from(["fim", "hash"]).is_err());
+        assert!(Cli::try_parse_from(["fim", "accept", "-m", "No targets"]).is_err());
+        assert!(Cli::try_parse_from(["fim", "accept", "/etc"]).is_err());
+    }
+}
@@ -6,18 +6,474 @@ pub const NETWORK_MODE: &str = "NETWORK";
 pub const FILE_MODE: &str = "FILE";
 pub const BOTH_MODE: &str = "BOTH";
 const CONFIG_LINUX_PATH: &str = "/etc/fim/config.yml";
+const MAX_FILE_SIZE: u64 = 1073741824;
+const BASELINE_UNIX_PATH: &str = "/var/lib/fim/baseline.json";
+const BASELINE_WINDOWS_PATH: &str = "C:\\ProgramData\\fim\\baseline.json";
+// Settings allowed at the root of the configuration file
+const SECTIONS: [&str; 8] = ["nodename", "events", "baseline", "scan", "hash", "heartbeat", "monitor", "log"];
+const DESTINATIONS: [&str; 3] = ["file", "network", "both"];
+pub const OUTPUT_TYPES: [&str; 4] = ["file", "elasticsearch", "syslog", "splunk"];
+pub const OUTPUT_FORMATS: [&str; 3] = ["json", "cef", "leef"];
+pub const OUTPUT_SCHEMAS: [&str; 2] = ["fim", "ecs"];
+pub const SYSLOG_PROTOCOLS: [&str; 4] = ["udp", "tcp", "tls", "unix"];
+const SYSLOG_RFCS: [u16; 2] = [5424, 3164];
+// Ordered by facility code
+pub const SYSLOG_FACILITIES: [&str; 24] = ["kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+    "uucp", "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clock",
+    "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7"];
+// Events kept while the endpoint is down, 100 MiB by default
+const SPOOL_MAX_SIZE: u64 = 104857600;
+pub const SPOOL_POLICIES: [&str; 2] = ["drop_oldest", "drop_newest"];
+// Endpoint requests are sent up to 5 times, waiting 500 ms before the first retry
+const RETRY_MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY: u64 = 500;
+pub const RETRY_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+// Spooled events are sent in bulk requests of up to 500 events or 5 MiB, at least every second
+const BATCH_MAX_EVENTS: usize = 500;
+const BATCH_MAX_BYTES: u64 = 5242880;
+const BATCH_INTERVAL: u64 = 1;
 
 // To parse files in yaml format
-use yaml_rust::yaml::{Yaml, YamlLoader, Array};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+// To get line numbers of configuration keys
+use yaml_rust::parser::{Parser, MarkedEventReceiver, Event};
+use yaml_rust::scanner::Marker;
 // To use files IO operations.
-use std::fs::{File, OpenOptions};
-use std::io::Read;
+use std::fs::{self, OpenOptions};
 use std::io::Write;
 // To manage paths
-use std::path::Path;
+use std::path::{Path, PathBuf};
+// To implement Display method
+use std::fmt;
+// To store line numbers of each key
+use std::collections::HashMap;
+// To stop the program on invalid configuration
+use std::process;
+// To resolve relative paths of the command line
+use std::env;
 // To set log filter level
 use simplelog::LevelFilter;
 
+use crate::hash;
+use crate::rules::{self, Rule};
+use crate::scan::Cron;
+
+// ----------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EventsConfig {
+    pub destination: Option<String>,
+    pub file: Option<String>,
+    pub ignore_unchanged: bool,
+    pub endpoint: Option<EndpointConfig>,
+    pub spool: SpoolConfig,
+    pub retry: RetryConfig,
+    pub batch: BatchConfig,
+    pub outputs: Option<Vec<OutputConfig>>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub format: Option<String>,
+    pub schema: Option<String>,
+    pub filter: FilterConfig,
+    // Settings of each type of output
+    pub file: Option<String>,
+    pub address: Option<String>,
+    pub insecure: Option<bool>,
+    pub credentials: CredentialsConfig,
+    pub protocol: Option<String>,
+    pub rfc: Option<u16>,
+    pub facility: Option<String>,
+    pub ca: Option<String>,
+    pub token: Option<String>,
+    pub index: Option<String>,
+    pub sourcetype: Option<String>,
+    pub source: Option<String>,
+    pub channel: Option<String>,
+    // Retry and batch settings of the events section are used if not set
+    pub retry: RetryConfig,
+    pub batch: OutputBatchConfig
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub kinds: Vec<String>,
+    pub labels: Vec<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SpoolConfig {
+    pub dir: Option<String>,
+    pub max_size: Option<u64>,
+    pub policy: Option<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BatchConfig {
+    pub max_events: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub interval: Option<u64>
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct OutputBatchConfig {
+    pub max_events: Option<usize>,
+    pub max_bytes: Option<u64>
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_attempts: Option<u32>,
+    pub base_delay: Option<u64>,
+    pub jitter: Option<bool>,
+    pub statuses: Option<Vec<u16>>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EndpointConfig {
+    pub address: Option<String>,
+    pub insecure: Option<bool>,
+    pub credentials: CredentialsConfig
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CredentialsConfig {
+    pub user: Option<String>,
+    pub password: Option<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct LogConfig {
+    pub file: Option<String>,
+    pub level: Option<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BaselineConfig {
+    pub file: Option<String>,
+    pub approved_file: Option<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HashConfig {
+    pub max_file_size: Option<u64>
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub interval: Option<u64>
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ScanConfig {
+    pub interval: Option<u64>,
+    pub schedule: Option<String>
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MonitorEntry {
+    pub path: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub ignore: Vec<Rule>,
+    #[serde(default)]
+    pub include: Vec<Rule>,
+    #[serde(default)]
+    pub algorithms: Vec<String>,
+    #[serde(default)]
+    pub scan: Option<ScanConfig>
+}
+
+// ----------------------------------------------------------------------------
+
+// Destination of events, only the settings of its type are set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    pub kind: String,
+    pub format: String,
+    // Fields of JSON events, the ones of FIM or Elastic Common Schema
+    pub schema: String,
+    // Events of other kinds or without any of these labels are not written, empty means all
+    pub kinds: Vec<String>,
+    pub labels: Vec<String>,
+    pub file: String,
+    pub address: String,
+    pub user: String,
+    pub pass: String,
+    pub insecure: bool,
+    pub protocol: String,
+    pub rfc: u16,
+    pub facility: String,
+    // Certificate file trusted by TLS connections besides system ones
+    pub ca: String,
+    pub token: String,
+    // Splunk index, empty uses the default one of the token
+    pub index: String,
+    pub sourcetype: String,
+    pub source: String,
+    // Indexer acknowledgement is used when it is set
+    pub channel: String,
+    // Global settings are used for the ones not set
+    pub retry: RetryConfig,
+    pub batch: OutputBatchConfig
+}
+
+impl Output {
+    pub fn new(name: &str, kind: &str) -> Self {
+        Output {
+            name: String::from(name),
+            kind: String::from(kind),
+            format: String::from(OUTPUT_FORMATS[0]),
+            schema: String::from(OUTPUT_SCHEMAS[0]),
+            kinds: Vec::new(),
+            labels: Vec::new(),
+            file: String::new(),
+            address: String::new(),
+            user: String::new(),
+            pass: String::new(),
+            insecure: false,
+            protocol: String::new(),
+            rfc: SYSLOG_RFCS[0],
+            facility: String::new(),
+            ca: String::new(),
+            token: String::new(),
+            index: String::new(),
+            sourcetype: String::new(),
+            source: String::new(),
+            channel: String::new(),
+            retry: RetryConfig::default(),
+            batch: OutputBatchConfig::default()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Problem found in the configuration file, line is not known on unreadable files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub line: Option<usize>,
+    pub key: String,
+    pub message: String
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.key.is_empty()) {
+            (Some(line), false) => write!(f, "line {}, {}: {}", line, self.key, self.message),
+            (Some(line), true) => write!(f, "line {}: {}", line, self.message),
+            (None, false) => write!(f, "{}: {}", self.key, self.message),
+            (None, true) => write!(f, "{}", self.message)
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: String,
+    pub issues: Vec<ConfigIssue>
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration file '{}':", self.path)?;
+        for issue in &self.issues {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// ----------------------------------------------------------------------------
+
+enum Frame {
+    // Path of the mapping and the key whose value is expected
+    Map(String, Option<String>),
+    // Path of the sequence and the index of the next item
+    Seq(String, usize)
+}
+
+// Line number of each key of a yaml document, like "events.file" or "monitor[0].path"
+#[derive(Default)]
+struct LineIndex {
+    lines: HashMap<String, usize>,
+    stack: Vec<Frame>
+}
+
+impl LineIndex {
+    fn new(contents: &str) -> Self {
+        let mut index = LineIndex::default();
+        // Syntax errors are reported by the deserializer
+        let _ = Parser::new(contents.chars()).load(&mut index, false);
+        index
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Register a node and get its path, scalars in key position are stored as keys
+    fn node(&mut self, scalar: Option<String>, mark: Marker) -> String {
+        match self.stack.last_mut() {
+            None => String::new(),
+            Some(Frame::Map(parent, key)) => match key.take() {
+                Some(key) => join_key(parent, &key),
+                None => {
+                    let name = scalar.unwrap_or_default();
+                    let path = join_key(parent, &name);
+                    self.lines.insert(path.clone(), mark.line());
+                    *key = Some(name);
+                    path
+                }
+            },
+            Some(Frame::Seq(parent, index)) => {
+                let path = format!("{}[{}]", parent, index);
+                *index += 1;
+                self.lines.insert(path.clone(), mark.line());
+                path
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the line of a key or of its nearest parent
+    fn get_line(&self, key: &str) -> Option<usize> {
+        let mut key = key;
+        loop {
+            if let Some(line) = self.lines.get(key) { return Some(*line); }
+            match key.rfind(['.', '[']) {
+                Some(position) => key = &key[..position],
+                None => return None
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineIndex {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => { self.node(Some(value), mark); },
+            Event::Alias(_) => { self.node(None, mark); },
+            Event::MappingStart(_) => {
+                let path = self.node(None, mark);
+                self.stack.push(Frame::Map(path, None));
+            },
+            Event::SequenceStart(_) => {
+                let path = self.node(None, mark);
+                self.stack.push(Frame::Seq(path, 0));
+            },
+            Event::MappingEnd | Event::SequenceEnd => { self.stack.pop(); },
+            _ => ()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn join_key(parent: &str, key: &str) -> String {
+    match parent.is_empty() {
+        true => String::from(key),
+        false => format!("{}.{}", parent, key)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Deserialize configuration sections collecting every problem found
+struct Loader {
+    lines: LineIndex,
+    issues: Vec<ConfigIssue>
+}
+
+impl Loader {
+    fn issue(&mut self, key: &str, message: &str) {
+        self.issues.push(ConfigIssue {
+            line: self.lines.get_line(key),
+            key: String::from(key),
+            message: String::from(message)
+        });
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Deserialize a value reporting unknown keys and the path of invalid ones
+    fn parse<T: DeserializeOwned>(&mut self, value: Value, key: &str) -> Option<T> {
+        let mut unknown = Vec::new();
+        let mut callback = |path: serde_ignored::Path| unknown.push(ignored_path(key, &path));
+        let deserializer = serde_ignored::Deserializer::new(value, &mut callback);
+        let result: Result<T, _> = serde_path_to_error::deserialize(deserializer);
+        for path in unknown {
+            self.issue(&path, "unknown key");
+        }
+        match result {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                let mut path = String::from(key);
+                for segment in e.path().iter() {
+                    path = match segment {
+                        serde_path_to_error::Segment::Seq { index } => format!("{}[{}]", path, index),
+                        serde_path_to_error::Segment::Map { key } => join_key(&path, key),
+                        serde_path_to_error::Segment::Enum { variant } => join_key(&path, variant),
+                        serde_path_to_error::Segment::Unknown => path
+                    };
+                }
+                self.issue(&path, &e.inner().to_string());
+                None
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Deserialize a section of the root mapping, None if it is not set
+    fn section<T: DeserializeOwned>(&mut self, root: &Value, key: &str) -> Option<T> {
+        match root.get(key) {
+            None | Some(Value::Null) => None,
+            Some(value) => self.parse(value.clone(), key)
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn ignored_path(prefix: &str, path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::from(prefix),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{}]", ignored_path(prefix, parent), index),
+        serde_ignored::Path::Map { parent, key } => join_key(&ignored_path(prefix, parent), key),
+        serde_ignored::Path::Some { parent } |
+        serde_ignored::Path::NewtypeStruct { parent } |
+        serde_ignored::Path::NewtypeVariant { parent } => ignored_path(prefix, parent)
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 pub struct Config {
@@ -28,10 +484,30 @@ pub struct Config {
     pub endpoint_user: String,
     pub endpoint_pass: String,
     pub events_file: String,
-    pub monitor: Array,
+    pub ignore_unchanged: bool,
+    pub baseline_file: String,
+    pub approved_file: String,
+    pub spool_dir: String,
+    pub spool_max_size: u64,
+    pub spool_policy: String,
+    pub retry_max_attempts: u32,
+    pub retry_base_delay: u64,
+    pub retry_jitter: bool,
+    pub retry_statuses: Vec<u16>,
+    pub batch_max_events: usize,
+    pub batch_max_bytes: u64,
+    pub batch_interval: u64,
+    pub scan_interval: u64,
+    pub scan_schedule: String,
+    pub max_file_size: u64,
+    pub heartbeat_interval: u64,
+    pub monitor: Vec<MonitorEntry>,
+    pub outputs: Vec<Output>,
     pub nodename: String,
     pub log_file: String,
     pub log_level: String,
+    // Level set in the command line, kept over the one of the file on reload
+    pub log_level_override: Option<String>,
     pub system: String,
     pub insecure: bool
 }
@@ -47,105 +523,258 @@ impl Config {
             endpoint_user: self.endpoint_user.clone(),
             endpoint_pass: self.endpoint_pass.clone(),
             events_file: self.events_file.clone(),
+            ignore_unchanged: self.ignore_unchanged,
+            baseline_file: self.baseline_file.clone(),
+            approved_file: self.approved_file.clone(),
+            spool_dir: self.spool_dir.clone(),
+            spool_max_size: self.spool_max_size,
+            spool_policy: self.spool_policy.clone(),
+            retry_max_attempts: self.retry_max_attempts,
+            retry_base_delay: self.retry_base_delay,
+            retry_jitter: self.retry_jitter,
+            retry_statuses: self.retry_statuses.clone(),
+            batch_max_events: self.batch_max_events,
+            batch_max_bytes: self.batch_max_bytes,
+            batch_interval: self.batch_interval,
+            scan_interval: self.scan_interval,
+            scan_schedule: self.scan_schedule.clone(),
+            max_file_size: self.max_file_size,
+            heartbeat_interval: self.heartbeat_interval,
             monitor: self.monitor.clone(),
+            outputs: self.outputs.clone(),
             nodename: self.nodename.clone(),
             log_file: self.log_file.clone(),
             log_level: self.log_level.clone(),
+            log_level_override: self.log_level_override.clone(),
             system: self.system.clone(),
             insecure: self.insecure
         }
     }
 
+    // ------------------------------------------------------------------------
+
+    // Load the configuration of the system, the program is stopped if it is not valid
     pub fn new(system: &str) -> Self {
+        Config::from_file(&get_config_path(system), system)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Load the given configuration file, the program is stopped if it is not valid
+    pub fn from_file(config_path: &str, system: &str) -> Self {
         println!("[INFO] System detected {}", system);
-        let config_path = get_config_path(system);
         println!("[INFO] Loaded config from: {}", config_path);
-        let yaml = read_config(config_path.clone());
+        match Config::load(config_path, system) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("[ERROR] {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Read and validate the given configuration file
+    pub fn load(config_path: &str, system: &str) -> Result<Self, ConfigError> {
+        let contents = match fs::read_to_string(config_path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(ConfigError {
+                path: String::from(config_path),
+                issues: vec![ConfigIssue {
+                    line: None, key: String::new(),
+                    message: format!("Unable to read file, error: {}", e)
+                }]
+            })
+        };
+        let mut loader = Loader { lines: LineIndex::new(&contents), issues: Vec::new() };
+
+        let root: Value = match serde_yaml::from_str(&contents) {
+            Ok(Value::Mapping(root)) => Value::Mapping(root),
+            Ok(_) => {
+                loader.issue("", "expected a map of settings");
+                return Err(ConfigError { path: String::from(config_path), issues: loader.issues });
+            },
+            Err(e) => {
+                loader.issues.push(ConfigIssue {
+                    line: e.location().map(|location| location.line()),
+                    key: String::new(),
+                    message: e.to_string()
+                });
+                return Err(ConfigError { path: String::from(config_path), issues: loader.issues });
+            }
+        };
+        for key in root.as_mapping().unwrap().keys() {
+            match key.as_str() {
+                Some(name) if SECTIONS.contains(&name) => (),
+                Some(name) => loader.issue(name, "unknown key"),
+                None => loader.issue("", "keys must be strings")
+            }
+        }
+
+        let nodename: Option<String> = loader.section(&root, "nodename");
+        let events: EventsConfig = loader.section(&root, "events").unwrap_or_default();
+        let baseline: BaselineConfig = loader.section(&root, "baseline").unwrap_or_default();
+        let scan: ScanConfig = loader.section(&root, "scan").unwrap_or_default();
+        let hash_config: HashConfig = loader.section(&root, "hash").unwrap_or_default();
+        let heartbeat: HeartbeatConfig = loader.section(&root, "heartbeat").unwrap_or_default();
+        let log: LogConfig = loader.section(&root, "log").unwrap_or_default();
+
+        // Monitor entries are read one by one to report problems of all of them
+        let monitor: Vec<Option<MonitorEntry>> = match root.get("monitor") {
+            Some(Value::Sequence(list)) => list.iter().enumerate().map(|(index, entry)| {
+                loader.parse(entry.clone(), &format!("monitor[{}]", index))
+            }).collect(),
+            Some(_) => {
+                loader.issue("monitor", "expected a list of monitor entries");
+                Vec::new()
+            },
+            None => {
+                loader.issue("monitor", "required key not found");
+                Vec::new()
+            }
+        };
+
+        // Destination, file and endpoint settings are used if outputs are not set
+        let legacy = events.outputs.is_none();
+        let outputs = match events.outputs {
+            Some(list) => load_outputs(&mut loader, list),
+            None => Vec::new()
+        };
 
         // Manage null value on events->destination value
-        let events_destination = match yaml[0]["events"]["destination"].as_str() {
-            Some(value) => String::from(value),
+        let events_destination = match events.destination {
+            Some(value) => {
+                if ! DESTINATIONS.contains(&value.as_str()) {
+                    loader.issue("events.destination", &format!("invalid value '{}', available: {}", value, DESTINATIONS.join(", ")));
+                }
+                value
+            },
             None => {
-                println!("[WARN] events->destination not found in config.yml, using 'file'.");
+                if legacy {
+                    println!("[WARN] events->destination not found in config.yml, using 'file'.");
+                }
                 String::from("file")
             }
         };
 
         // Manage null value on events->file value
-        let events_file = match yaml[0]["events"]["file"].as_str() {
-            Some(value) => String::from(value),
+        let events_file = match events.file {
+            Some(value) => value,
             None => {
-                if events_destination != *"network" {
-                    println!("[ERROR] events->file not found in config.yml.");
-                    panic!("events->file not found in config.yml.");
-                }else{
-                    String::from("Not_used")
+                if legacy && events_destination != *"network" {
+                    loader.issue("events.file", "required key not found");
                 }
+                String::from("Not_used")
             }
         };
 
+        let endpoint = events.endpoint.unwrap_or_default();
+        let network = legacy && events_destination != *"file";
+
         // Manage null value on events->endpoint->insecure value
-        let insecure = match yaml[0]["events"]["endpoint"]["insecure"].as_bool() {
+        let insecure = match endpoint.insecure {
             Some(value) => value,
             None => {
-                if events_destination != *"file" {
+                if network {
                     println!("[WARN] events->endpoint->insecure not found in config.yml, using 'false'.");
-                    false
-                }else{ false }
+                }
+                false
             }
         };
 
-        // Manage null value on events->endpoint->address value
-        let endpoint_address = match yaml[0]["events"]["endpoint"]["address"].as_str() {
-            Some(value) => String::from(value),
+        // Endpoint settings are required to send events through network
+        let mut get_endpoint_value = |value: Option<String>, key: &str| match value {
+            Some(value) => value,
             None => {
-                if events_destination != *"file" {
-                    println!("[ERROR] events->endpoint->address not found in config.yml.");
-                    panic!("events->endpoint->address not found in config.yml.");
-                }else{
-                    String::from("Not_used")
-                }
+                if network { loader.issue(key, "required key not found"); }
+                String::from("Not_used")
             }
         };
+        let endpoint_address = get_endpoint_value(endpoint.address, "events.endpoint.address");
+        let endpoint_user = get_endpoint_value(endpoint.credentials.user, "events.endpoint.credentials.user");
+        let endpoint_pass = get_endpoint_value(endpoint.credentials.password, "events.endpoint.credentials.password");
 
-        // Manage null value on events->endpoint->credentials->user value
-        let endpoint_user = match yaml[0]["events"]["endpoint"]["credentials"]["user"].as_str() {
-            Some(value) => String::from(value),
+        // Manage null value on baseline->file value
+        let baseline_file = match baseline.file {
+            Some(value) => value,
             None => {
-                if events_destination != *"file" {
-                    println!("[ERROR] events->endpoint->credentials->user not found in config.yml.");
-                    panic!("events->endpoint->credentials->user not found in config.yml.");
-                }else{
-                    String::from("Not_used")
-                }
+                let default_path = match system {
+                    "windows" => BASELINE_WINDOWS_PATH,
+                    _ => BASELINE_UNIX_PATH
+                };
+                println!("[WARN] baseline->file not found in config.yml, using '{}'.", default_path);
+                String::from(default_path)
             }
         };
 
-        // Manage null value on events->endpoint->credentials->password value
-        let endpoint_pass = match yaml[0]["events"]["endpoint"]["credentials"]["password"].as_str() {
-            Some(value) => String::from(value),
-            None => {
-                if events_destination != *"file" {
-                    println!("[ERROR] events->endpoint->credentials->password not found in config.yml.");
-                    panic!("events->endpoint->credentials->password not found in config.yml.");
-                }else{
-                    String::from("Not_used")
-                }
-            }
+        // Last approved state is stored next to the baseline by default
+        let approved_file = match baseline.approved_file {
+            Some(value) => value,
+            None => String::from(Path::new(&baseline_file).with_extension("approved.json").to_str().unwrap())
         };
 
-        // Manage null value on monitor value
-        let monitor = match yaml[0]["monitor"].as_vec() {
-            Some(value) => value.to_vec(),
-            None => {
-                println!("[ERROR] monitor not found in config.yml.");
-                panic!("monitor not found in config.yml.");
-            }
+        // Events waiting to be delivered are stored next to the baseline by default
+        let spool_dir = match events.spool.dir {
+            Some(value) => value,
+            None => String::from(Path::new(&baseline_file).with_file_name("spool").to_str().unwrap())
         };
+        let spool_max_size = events.spool.max_size.unwrap_or(SPOOL_MAX_SIZE);
+        let spool_policy = events.spool.policy.unwrap_or_else(|| String::from(SPOOL_POLICIES[0]));
+        if ! SPOOL_POLICIES.contains(&spool_policy.as_str()) {
+            loader.issue("events.spool.policy", &format!("invalid value '{}', available: {}", spool_policy, SPOOL_POLICIES.join(", ")));
+        }
+
+        // Failed endpoint requests are sent again waiting longer each time
+        let retry_max_attempts = events.retry.max_attempts.unwrap_or(RETRY_MAX_ATTEMPTS);
+        if retry_max_attempts == 0 {
+            loader.issue("events.retry.max_attempts", "invalid value '0', at least one attempt is required");
+        }
+        let retry_base_delay = events.retry.base_delay.unwrap_or(RETRY_BASE_DELAY);
+        let retry_jitter = events.retry.jitter.unwrap_or(true);
+        let retry_statuses = events.retry.statuses.unwrap_or_else(|| RETRY_STATUSES.to_vec());
+        if let Some(status) = retry_statuses.iter().find(|status| ! (100..600).contains(*status)) {
+            loader.issue("events.retry.statuses", &format!("invalid HTTP status '{}'", status));
+        }
+
+        // Network events are grouped in bulk requests
+        let batch_max_events = events.batch.max_events.unwrap_or(BATCH_MAX_EVENTS);
+        if batch_max_events == 0 {
+            loader.issue("events.batch.max_events", "invalid value '0', at least one event is required");
+        }
+        let batch_max_bytes = events.batch.max_bytes.unwrap_or(BATCH_MAX_BYTES);
+        let batch_interval = events.batch.interval.unwrap_or(BATCH_INTERVAL);
+
+        // Periodic scans disabled by default
+        let scan_interval = scan.interval.unwrap_or(0);
+        let scan_schedule = scan.schedule.unwrap_or_default();
+        if let Err(e) = check_schedule(&scan_schedule) {
+            loader.issue("scan.schedule", &e);
+        }
+
+        let max_file_size = hash_config.max_file_size.unwrap_or(MAX_FILE_SIZE);
+        // Heartbeat events disabled by default
+        let heartbeat_interval = heartbeat.interval.unwrap_or(0);
+
+        for (index, entry) in monitor.iter().enumerate() {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => continue
+            };
+            for algorithm in &entry.algorithms {
+                if ! hash::ALGORITHMS.contains(&algorithm.to_lowercase().as_str()) {
+                    loader.issue(&format!("monitor[{}].algorithms", index),
+                        &format!("hash algorithm '{}' not supported, available: {}", algorithm, hash::ALGORITHMS.join(", ")));
+                }
+            }
+            if let Some(Err(e)) = entry.scan.as_ref().and_then(|scan| scan.schedule.as_ref()).map(|schedule| check_schedule(schedule)) {
+                loader.issue(&format!("monitor[{}].scan.schedule", index), &e);
+            }
+        }
 
         // Manage null value on nodename value
-        let nodename = match yaml[0]["nodename"].as_str() {
-            Some(value) => String::from(value),
+        let nodename = match nodename {
+            Some(value) => value,
             None => {
                 println!("[WARN] nodename not found in config.yml, using 'FIM'.");
                 String::from("FIM")
@@ -153,43 +782,72 @@ impl Config {
         };
 
         // Manage null value on log->file value
-        let log_file = match yaml[0]["log"]["file"].as_str() {
-            Some(value) => String::from(value),
+        let log_file = match log.file {
+            Some(value) => value,
             None => {
-                println!("[ERROR] log->file not found in config.yml.");
-                panic!("log->file not found in config.yml.");
+                loader.issue("log.file", "required key not found");
+                String::new()
             }
         };
 
         // Manage null value on log->level value
-        let log_level = match yaml[0]["log"]["level"].as_str() {
-            Some(value) => String::from(value),
+        let log_level = match log.level {
+            Some(value) => {
+                if get_level(&value).is_none() {
+                    loader.issue("log.level", &format!("invalid value '{}', available: debug, info, error, warning", value));
+                }
+                value
+            },
             None => {
                 println!("[WARN] log->level not found in config.yml, using 'info'.");
                 String::from("info")
             }
         };
 
-        Config {
+        if ! loader.issues.is_empty() {
+            return Err(ConfigError { path: String::from(config_path), issues: loader.issues });
+        }
+
+        Ok(Config {
             version: String::from(VERSION),
-            path: config_path,
+            path: String::from(config_path),
             events_destination,
             endpoint_address,
             endpoint_user,
             endpoint_pass,
             events_file,
-            monitor,
+            ignore_unchanged: events.ignore_unchanged,
+            baseline_file,
+            approved_file,
+            spool_dir,
+            spool_max_size,
+            spool_policy,
+            retry_max_attempts,
+            retry_base_delay,
+            retry_jitter,
+            retry_statuses,
+            batch_max_events,
+            batch_max_bytes,
+            batch_interval,
+            scan_interval,
+            scan_schedule,
+            max_file_size,
+            heartbeat_interval,
+            monitor: monitor.into_iter().flatten().collect(),
+            outputs,
             nodename,
             log_file,
             log_level,
+            log_level_override: None,
             system: String::from(system),
             insecure
-        }
+        })
     }
 
     // ------------------------------------------------------------------------
 
     // To process log level set on config file
+    #[allow(clippy::ineffective_open_options)]
     pub fn get_level_filter(&self) -> LevelFilter {
         let mut log = OpenOptions::new()
             .create(true)
@@ -198,12 +856,9 @@ impl Config {
             .open(self.log_file.clone())
             .expect("(get_level_filter) Unable to open events log file.");
 
-        match self.log_level.as_str() {
-            "debug" | "Debug" | "DEBUG" | "D" | "d" => LevelFilter::Debug,
-            "info" | "Info" | "INFO" | "I" | "i" => LevelFilter::Info,
-            "error" | "Error" | "ERROR" | "E" | "e" => LevelFilter::Error,
-            "warning" | "Warning" | "WARNING" | "W" | "w" | "warn" | "Warn" | "WARN" => LevelFilter::Warn,
-            _ => {
+        match get_level(&self.log_level) {
+            Some(level) => level,
+            None => {
                 let msg = String::from("[ERROR] invalid log level from 'config.yml', using Info level.");
                 println!("{}", msg);
                 writeln!(log, "{}", msg).expect("[ERROR] cannot write in log file.");
@@ -223,18 +878,246 @@ impl Config {
         }
     }
 
+    // ------------------------------------------------------------------------
+
+    // Get the configured outputs, the ones of the events destination if they are not set
+    pub fn get_outputs(&self) -> Vec<Output> {
+        if ! self.outputs.is_empty() {
+            return self.outputs.clone();
+        }
+        let destination = self.get_events_destination();
+        let mut outputs = Vec::new();
+        if destination != NETWORK_MODE {
+            let mut output = Output::new("file", "file");
+            output.file = self.events_file.clone();
+            outputs.push(output);
+        }
+        if destination != FILE_MODE {
+            let mut output = Output::new("network", "elasticsearch");
+            output.address = self.endpoint_address.clone();
+            output.user = self.endpoint_user.clone();
+            output.pass = self.endpoint_pass.clone();
+            output.insecure = self.insecure;
+            outputs.push(output);
+        }
+        outputs
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the maximum events and bytes of the batches of an output, global values are used if not set
+    pub fn get_batch(&self, output: &Output) -> (usize, u64) {
+        (output.batch.max_events.unwrap_or(self.batch_max_events), output.batch.max_bytes.unwrap_or(self.batch_max_bytes))
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the monitor entry of the given path, the deepest monitored path containing it
+    // is used so nested entries keep their own labels and rules
+    pub fn get_index(&self, path: &Path) -> Option<usize> {
+        self.monitor.iter().enumerate().rev()
+            .filter(|(_, entry)| path.starts_with(&entry.path))
+            .max_by_key(|(_, entry)| Path::new(&entry.path).components().count())
+            .map(|(index, _)| index)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the monitor entry of each given path, relative ones are resolved from the
+    // current directory. All monitored paths are returned if none is given
+    pub fn get_roots(&self, paths: &[String]) -> Result<Vec<(usize, PathBuf)>, String> {
+        if paths.is_empty() {
+            return Ok(self.monitor.iter().enumerate()
+                .map(|(index, entry)| (index, PathBuf::from(&entry.path))).collect());
+        }
+        paths.iter().map(|value| {
+            let root = match Path::new(value).is_absolute() {
+                true => PathBuf::from(value),
+                false => env::current_dir().unwrap().join(value)
+            };
+            match self.get_index(&root) {
+                Some(index) => Ok((index, root)),
+                None => Err(format!("Path '{}' is not inside any monitored path", value))
+            }
+        }).collect()
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Check if path matches the ignore rules or is out of the include rules of the given monitor entry
+    pub fn match_ignore(&self, index: usize, path: &Path) -> bool {
+        let entry = &self.monitor[index];
+        rules::is_ignored(&entry.ignore, &entry.include, path)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get scan interval and schedule of the given monitor entry, global values are used if not set
+    pub fn get_scan(&self, index: usize) -> (u64, String) {
+        match &self.monitor[index].scan {
+            Some(scan) => {
+                let interval = scan.interval.unwrap_or(self.scan_interval);
+                let schedule = match (&scan.schedule, scan.interval) {
+                    (Some(schedule), _) => schedule.clone(),
+                    (None, None) => self.scan_schedule.clone(),
+                    // An interval set in the entry replaces the global schedule
+                    (None, Some(_)) => String::new()
+                };
+                (interval, schedule)
+            },
+            None => (self.scan_interval, self.scan_schedule.clone())
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get hash algorithms of the given monitor entry, the default one if not set
+    pub fn get_algorithms(&self, index: usize) -> Vec<String> {
+        match self.monitor[index].algorithms.is_empty() {
+            false => self.monitor[index].algorithms.iter().map(|algorithm| algorithm.to_lowercase()).collect(),
+            true => vec![String::from(hash::DEFAULT_ALGORITHM)]
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get labels list of the given monitor entry
+    pub fn get_labels(&self, index: usize) -> Vec<String> {
+        self.monitor[index].labels.clone()
+    }
+
 }
 
 // ----------------------------------------------------------------------------
 
-// To read the Yaml configuration file
-pub fn read_config(path: String) -> Vec<Yaml> {
-    let mut file = File::open(path).expect("Unable to open file");
-    let mut contents = String::new();
+// Get the log filter of a level name, None if it is not valid
+pub fn get_level(level: &str) -> Option<LevelFilter> {
+    match level {
+        "debug" | "Debug" | "DEBUG" | "D" | "d" => Some(LevelFilter::Debug),
+        "info" | "Info" | "INFO" | "I" | "i" => Some(LevelFilter::Info),
+        "error" | "Error" | "ERROR" | "E" | "e" => Some(LevelFilter::Error),
+        "warning" | "Warning" | "WARNING" | "W" | "w" | "warn" | "Warn" | "WARN" => Some(LevelFilter::Warn),
+        _ => None
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Check the settings of each output, required ones depend on its type
+fn load_outputs(loader: &mut Loader, list: Vec<OutputConfig>) -> Vec<Output> {
+    let mut outputs: Vec<Output> = Vec::new();
+    for (index, config) in list.into_iter().enumerate() {
+        let key = format!("events.outputs[{}]", index);
+        let kind = match config.kind {
+            Some(kind) if OUTPUT_TYPES.contains(&kind.as_str()) => kind,
+            Some(kind) => {
+                loader.issue(&format!("{}.type", key), &format!("invalid value '{}', available: {}", kind, OUTPUT_TYPES.join(", ")));
+                continue;
+            },
+            None => {
+                loader.issue(&key, "required key 'type' not found");
+                continue;
+            }
+        };
+        let mut output = Output::new(&config.name.unwrap_or_else(|| kind.clone()), &kind);
+        if outputs.iter().any(|previous| previous.name == output.name) {
+            loader.issue(&format!("{}.name", key), &format!("duplicated output name '{}'", output.name));
+        }
+        if let Some(format) = config.format {
+            if ! OUTPUT_FORMATS.contains(&format.as_str()) {
+                loader.issue(&format!("{}.format", key), &format!("invalid value '{}', available: {}", format, OUTPUT_FORMATS.join(", ")));
+            }
+            output.format = format;
+        }
+        if let Some(schema) = config.schema {
+            if ! OUTPUT_SCHEMAS.contains(&schema.as_str()) {
+                loader.issue(&format!("{}.schema", key), &format!("invalid value '{}', available: {}", schema, OUTPUT_SCHEMAS.join(", ")));
+            }else if schema != "fim" && output.format != "json" {
+                loader.issue(&format!("{}.schema", key), "only json format supports the ecs schema");
+            }else if schema != "fim" && ! ["file", "elasticsearch"].contains(&kind.as_str()) {
+                loader.issue(&format!("{}.schema", key), "only file and elasticsearch outputs support the ecs schema");
+            }
+            output.schema = schema;
+        }
+        output.kinds = config.filter.kinds;
+        output.labels = config.filter.labels;
+        if config.retry.max_attempts == Some(0) {
+            loader.issue(&format!("{}.retry.max_attempts", key), "invalid value '0', at least one attempt is required");
+        }
+        if let Some(status) = config.retry.statuses.iter().flatten().find(|status| ! (100..600).contains(*status)) {
+            loader.issue(&format!("{}.retry.statuses", key), &format!("invalid HTTP status '{}'", status));
+        }
+        if config.batch.max_events == Some(0) {
+            loader.issue(&format!("{}.batch.max_events", key), "invalid value '0', at least one event is required");
+        }
+        output.retry = config.retry;
+        output.batch = config.batch;
+
+        let mut required = |value: Option<String>, name: &str| value.unwrap_or_else(|| {
+            loader.issue(&key, &format!("required key '{}' not found", name));
+            String::new()
+        });
+        match kind.as_str() {
+            "file" => output.file = required(config.file, "file"),
+            "syslog" => {
+                output.protocol = config.protocol.unwrap_or_else(|| String::from(SYSLOG_PROTOCOLS[0]));
+                // Local daemon socket is used by default
+                output.address = match output.protocol.as_str() {
+                    "unix" => config.address.unwrap_or_else(|| String::from("/dev/log")),
+                    _ => required(config.address, "address")
+                };
+                if ! SYSLOG_PROTOCOLS.contains(&output.protocol.as_str()) {
+                    loader.issue(&format!("{}.protocol", key), &format!("invalid value '{}', available: {}", output.protocol, SYSLOG_PROTOCOLS.join(", ")));
+                }
+                output.rfc = config.rfc.unwrap_or(SYSLOG_RFCS[0]);
+                if ! SYSLOG_RFCS.contains(&output.rfc) {
+                    loader.issue(&format!("{}.rfc", key), &format!("invalid value '{}', available: 5424, 3164", output.rfc));
+                }
+                output.facility = config.facility.unwrap_or_else(|| String::from("local0"));
+                if ! SYSLOG_FACILITIES.contains(&output.facility.as_str()) {
+                    loader.issue(&format!("{}.facility", key), &format!("invalid value '{}', available: {}", output.facility, SYSLOG_FACILITIES.join(", ")));
+                }
+                output.ca = config.ca.unwrap_or_default();
+                output.insecure = config.insecure.unwrap_or(false);
+            },
+            "splunk" => {
+                output.address = required(config.address, "address");
+                output.token = required(config.token, "token");
+                output.index = config.index.unwrap_or_default();
+                output.sourcetype = config.sourcetype.unwrap_or_else(|| match output.format.as_str() {
+                    "json" => String::from("_json"),
+                    format => String::from(format)
+                });
+                output.source = config.source.unwrap_or_else(|| String::from("fim"));
+                output.channel = config.channel.unwrap_or_default();
+                if ! output.channel.is_empty() && uuid::Uuid::parse_str(&output.channel).is_err() {
+                    loader.issue(&format!("{}.channel", key), &format!("invalid value '{}', a GUID is required", output.channel));
+                }
+                output.insecure = config.insecure.unwrap_or(false);
+            },
+            _ => {
+                output.address = required(config.address, "address");
+                output.user = required(config.credentials.user, "credentials.user");
+                output.pass = required(config.credentials.password, "credentials.password");
+                output.insecure = config.insecure.unwrap_or(false);
+                if output.format != "json" {
+                    loader.issue(&format!("{}.format", key), "only json format is supported by elasticsearch outputs");
+                }
+            }
+        }
+        outputs.push(output);
+    }
+    outputs
+}
+
+// ----------------------------------------------------------------------------
 
-    file.read_to_string(&mut contents)
-        .expect("Unable to read file");
-    YamlLoader::load_from_str(&contents).unwrap()
+// Empty schedules disable cron scans
+fn check_schedule(schedule: &str) -> Result<(), String> {
+    match schedule.is_empty() {
+        true => Ok(()),
+        false => Cron::parse(schedule).map(|_| ())
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -257,6 +1140,7 @@ pub fn get_config_path(system: &str) -> String {
 // ----------------------------------------------------------------------------
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     // To use files IO operations.
@@ -273,10 +1157,29 @@ mod tests {
             endpoint_user: String::from("test"),
             endpoint_pass: String::from("test"),
             events_file: String::from("test"),
-            monitor: Array::new(),
+            ignore_unchanged: false,
+            baseline_file: String::from("test"),
+            approved_file: String::from("test"),
+            spool_dir: String::from("test"),
+            spool_max_size: 0,
+            spool_policy: String::from("drop_oldest"),
+            retry_max_attempts: 1,
+            retry_base_delay: 0,
+            retry_jitter: false,
+            retry_statuses: Vec::new(),
+            batch_max_events: 1,
+            batch_max_bytes: 0,
+            batch_interval: 0,
+            scan_interval: 0,
+            scan_schedule: String::new(),
+            max_file_size: 0,
+            heartbeat_interval: 0,
+            monitor: Vec::new(),
+            outputs: Vec::new(),
             nodename: String::from("test"),
             log_file: String::from("./test.log"),
             log_level: String::from(filter),
+            log_level_override: None,
             system: String::from("test"),
             insecure: true
         }
@@ -295,10 +1198,29 @@ mod tests {
         assert_eq!(config.endpoint_user, cloned.endpoint_user);
         assert_eq!(config.endpoint_pass, cloned.endpoint_pass);
         assert_eq!(config.events_file, cloned.events_file);
+        assert_eq!(config.ignore_unchanged, cloned.ignore_unchanged);
+        assert_eq!(config.baseline_file, cloned.baseline_file);
+        assert_eq!(config.approved_file, cloned.approved_file);
+        assert_eq!(config.spool_dir, cloned.spool_dir);
+        assert_eq!(config.spool_max_size, cloned.spool_max_size);
+        assert_eq!(config.spool_policy, cloned.spool_policy);
+        assert_eq!(config.retry_max_attempts, cloned.retry_max_attempts);
+        assert_eq!(config.retry_base_delay, cloned.retry_base_delay);
+        assert_eq!(config.retry_jitter, cloned.retry_jitter);
+        assert_eq!(config.retry_statuses, cloned.retry_statuses);
+        assert_eq!(config.batch_max_events, cloned.batch_max_events);
+        assert_eq!(config.batch_max_bytes, cloned.batch_max_bytes);
+        assert_eq!(config.batch_interval, cloned.batch_interval);
+        assert_eq!(config.scan_interval, cloned.scan_interval);
+        assert_eq!(config.scan_schedule, cloned.scan_schedule);
+        assert_eq!(config.max_file_size, cloned.max_file_size);
+        assert_eq!(config.heartbeat_interval, cloned.heartbeat_interval);
         assert_eq!(config.monitor, cloned.monitor);
+        assert_eq!(config.outputs, cloned.outputs);
         assert_eq!(config.nodename, cloned.nodename);
         assert_eq!(config.log_file, cloned.log_file);
         assert_eq!(config.log_level, cloned.log_level);
+        assert_eq!(config.log_level_override, cloned.log_level_override);
         assert_eq!(config.system, cloned.system);
         assert_eq!(config.insecure, cloned.insecure);
     }
@@ -314,12 +1236,27 @@ mod tests {
         assert_eq!(config.endpoint_user, String::from("Not_used"));
         assert_eq!(config.endpoint_pass, String::from("Not_used"));
         assert_eq!(config.events_file, String::from("C:\\ProgramData\\fim\\events.json"));
+        assert_eq!(config.baseline_file, String::from("C:\\ProgramData\\fim\\baseline.json"));
+        assert_eq!(config.approved_file, String::from("C:\\ProgramData\\fim\\baseline.approved.json"));
+        assert_eq!(config.spool_dir, String::from("C:\\ProgramData\\fim\\spool"));
+        assert_eq!(config.spool_max_size, 104857600);
+        assert_eq!(config.spool_policy, String::from("drop_oldest"));
+        assert_eq!(config.retry_max_attempts, 5);
+        assert_eq!(config.retry_base_delay, 500);
+        assert!(config.retry_jitter);
+        assert_eq!(config.retry_statuses, vec![408, 429, 500, 502, 503, 504]);
+        assert_eq!(config.batch_max_events, 500);
+        assert_eq!(config.batch_max_bytes, 5242880);
+        assert_eq!(config.batch_interval, 1);
         // monitor
+        assert_eq!(config.max_file_size, 1073741824);
+        assert_eq!(config.heartbeat_interval, 300);
         assert_eq!(config.nodename, String::from("FIM"));
         assert_eq!(config.log_file, String::from("C:\\ProgramData\\fim\\fim.log"));
         assert_eq!(config.log_level, String::from("info"));
         assert_eq!(config.system, String::from("windows"));
         assert_eq!(config.insecure, false);
+        assert!(!config.ignore_unchanged);
     }
 
     // ------------------------------------------------------------------------
@@ -333,12 +1270,27 @@ mod tests {
         assert_eq!(config.endpoint_user, String::from("Not_used"));
         assert_eq!(config.endpoint_pass, String::from("Not_used"));
         assert_eq!(config.events_file, String::from("/var/lib/fim/events.json"));
+        assert_eq!(config.baseline_file, String::from("/var/lib/fim/baseline.json"));
+        assert_eq!(config.approved_file, String::from("/var/lib/fim/baseline.approved.json"));
+        assert_eq!(config.spool_dir, String::from("/var/lib/fim/spool"));
+        assert_eq!(config.spool_max_size, 104857600);
+        assert_eq!(config.spool_policy, String::from("drop_oldest"));
+        assert_eq!(config.retry_max_attempts, 5);
+        assert_eq!(config.retry_base_delay, 500);
+        assert!(config.retry_jitter);
+        assert_eq!(config.retry_statuses, vec![408, 429, 500, 502, 503, 504]);
+        assert_eq!(config.batch_max_events, 500);
+        assert_eq!(config.batch_max_bytes, 5242880);
+        assert_eq!(config.batch_interval, 1);
         // monitor
+        assert_eq!(config.max_file_size, 1073741824);
+        assert_eq!(config.heartbeat_interval, 300);
         assert_eq!(config.nodename, String::from("FIM"));
         assert_eq!(config.log_file, String::from("/var/log/fim/fim.log"));
         assert_eq!(config.log_level, String::from("info"));
         assert_eq!(config.system, String::from("linux"));
         assert_eq!(config.insecure, false);
+        assert!(!config.ignore_unchanged);
     }
 
     // ------------------------------------------------------------------------
@@ -352,12 +1304,27 @@ mod tests {
         assert_eq!(config.endpoint_user, String::from("Not_used"));
         assert_eq!(config.endpoint_pass, String::from("Not_used"));
         assert_eq!(config.events_file, String::from("/var/lib/fim/events.json"));
+        assert_eq!(config.baseline_file, String::from("/var/lib/fim/baseline.json"));
+        assert_eq!(config.approved_file, String::from("/var/lib/fim/baseline.approved.json"));
+        assert_eq!(config.spool_dir, String::from("/var/lib/fim/spool"));
+        assert_eq!(config.spool_max_size, 104857600);
+        assert_eq!(config.spool_policy, String::from("drop_oldest"));
+        assert_eq!(config.retry_max_attempts, 5);
+        assert_eq!(config.retry_base_delay, 500);
+        assert!(config.retry_jitter);
+        assert_eq!(config.retry_statuses, vec![408, 429, 500, 502, 503, 504]);
+        assert_eq!(config.batch_max_events, 500);
+        assert_eq!(config.batch_max_bytes, 5242880);
+        assert_eq!(config.batch_interval, 1);
         // monitor
+        assert_eq!(config.max_file_size, 1073741824);
+        assert_eq!(config.heartbeat_interval, 300);
         assert_eq!(config.nodename, String::from("FIM"));
         assert_eq!(config.log_file, String::from("/var/log/fim/fim.log"));
         assert_eq!(config.log_level, String::from("info"));
         assert_eq!(config.system, String::from("macos"));
         assert_eq!(config.insecure, false);
+        assert!(!config.ignore_unchanged);
     }
 
     // ------------------------------------------------------------------------
@@ -448,61 +1415,456 @@ mod tests {
     // ------------------------------------------------------------------------
 
     #[test]
-    fn test_read_config_unix() {
-        let yaml = read_config(String::from("config/linux/config.yml"));
+    fn test_get_outputs() {
+        let outputs = create_test_config("info", "both").get_outputs();
+        assert_eq!(outputs.iter().map(|output| output.name.as_str()).collect::<Vec<&str>>(), vec!["file", "network"]);
+        assert_eq!(outputs[0].kind, "file");
+        assert_eq!(outputs[1].kind, "elasticsearch");
+        assert_eq!(outputs[1].format, "json");
+        assert_eq!(create_test_config("info", "file").get_outputs().len(), 1);
+        assert_eq!(create_test_config("info", "network").get_outputs()[0].name, "network");
+
+        let mut config = create_test_config("info", "both");
+        config.outputs = vec![Output::new("audit", "file")];
+        assert_eq!(config.get_outputs(), vec![Output::new("audit", "file")]);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_index() {
+        let mut config = create_test_config("info", "");
+        let yaml = "- path: /bin/\n- path: /usr/bin\n- path: /etc\n- path: /etc/ssh/\n- path: /etc/hosts\n- path: /etc/";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.get_index(Path::new("/bin/ls")), Some(0));
+        assert_eq!(config.get_index(Path::new("/usr/bin/ls")), Some(1));
+        assert_eq!(config.get_index(Path::new("/etc/passwd")), Some(2));
+        assert_eq!(config.get_index(Path::new("/etc/ssh/sshd_config")), Some(3));
+        assert_eq!(config.get_index(Path::new("/etc/ssh")), Some(3));
+        assert_eq!(config.get_index(Path::new("/etc/hosts")), Some(4));
+        assert_eq!(config.get_index(Path::new("/etc/hosts.allow")), Some(2));
+        assert_eq!(config.get_index(Path::new("/etcetera/file")), None);
+        assert_eq!(config.get_index(Path::new("/binary")), None);
+        assert_eq!(config.get_index(Path::new("/var/file")), None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_roots() {
+        let mut config = create_test_config("info", "");
+        let current = env::current_dir().unwrap();
+        let yaml = format!("- path: /etc\n- path: {}", current.join("test").to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.get_roots(&[]).unwrap(), vec![(0, PathBuf::from("/etc")), (1, current.join("test"))]);
+        assert_eq!(config.get_roots(&[String::from("/etc/ssh"), String::from("test/dir")]).unwrap(),
+            vec![(0, PathBuf::from("/etc/ssh")), (1, current.join("test/dir"))]);
+        assert!(config.get_roots(&[String::from("/etc"), String::from("/var")]).is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_match_ignore() {
+        let mut config = create_test_config("info", "");
+        let yaml = "- path: /tmp/\n  ignore: [\".swp\", \"~\"]\n- path: /etc\n- path: /var/log\n  ignore: [glob: \"*.gz\"]\n- path: /opt/app\n  include: [regex: \"\\\\.conf$\"]";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.match_ignore(0, Path::new("/tmp/file.swp")));
+        assert!(config.match_ignore(0, Path::new("/tmp/file~")));
+        assert!(!config.match_ignore(0, Path::new("/tmp/file.txt")));
+        assert!(!config.match_ignore(1, Path::new("/etc/file.swp")));
+        assert!(config.match_ignore(2, Path::new("/var/log/syslog.gz")));
+        assert!(!config.match_ignore(2, Path::new("/var/log/syslog")));
+        assert!(config.match_ignore(3, Path::new("/opt/app/data.bin")));
+        assert!(!config.match_ignore(3, Path::new("/opt/app/app.conf")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_scan() {
+        let mut config = create_test_config("info", "");
+        let yaml = "- path: /tmp/\n  scan:\n    interval: 60\n- path: /etc\n  scan:\n    schedule: \"0 3 * * *\"\n- path: /bin/";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.get_scan(0), (60, String::new()));
+        assert_eq!(config.get_scan(1), (0, String::from("0 3 * * *")));
+        assert_eq!(config.get_scan(2), (0, String::new()));
+
+        config.scan_interval = 3600;
+        config.scan_schedule = String::from("*/5 * * * *");
+        assert_eq!(config.get_scan(0), (60, String::new()));
+        assert_eq!(config.get_scan(1), (3600, String::from("0 3 * * *")));
+        assert_eq!(config.get_scan(2), (3600, String::from("*/5 * * * *")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_algorithms() {
+        let mut config = create_test_config("info", "");
+        let yaml = "- path: /tmp/\n  algorithms: [\"SHA256\", \"md5\"]\n- path: /etc";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.get_algorithms(0), vec![String::from("sha256"), String::from("md5")]);
+        assert_eq!(config.get_algorithms(1), vec![String::from("sha3-512")]);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_labels() {
+        let mut config = create_test_config("info", "");
+        let yaml = "- path: /tmp/\n  labels: [\"tmp\", \"linux\"]\n- path: /etc";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.get_labels(0), vec![String::from("tmp"), String::from("linux")]);
+        assert_eq!(config.get_labels(1), Vec::<String>::new());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_load_unix() {
+        let config = Config::load("config/linux/config.yml", "linux").unwrap();
+        assert_eq!(config.path, String::from("config/linux/config.yml"));
+        assert_eq!(config.monitor.len(), 4);
+        assert_eq!(config.monitor[0].path, String::from("/tmp/"));
+        assert_eq!(config.monitor[1].path, String::from("/bin/"));
+        assert_eq!(config.monitor[2].path, String::from("/usr/bin/"));
+        assert_eq!(config.monitor[2].labels, vec![String::from("usr/bin"), String::from("linux")]);
+        assert_eq!(config.monitor[3].path, String::from("/etc"));
+        assert_eq!(config.monitor[3].labels, vec![String::from("etc"), String::from("linux")]);
+        assert!(config.monitor[3].ignore.is_empty());
+        assert_eq!(config.monitor[3].scan, None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_load_windows() {
+        let config = Config::load("config/windows/config.yml", "windows").unwrap();
+        assert_eq!(config.monitor[0].path, String::from("C:\\Program Files\\"));
+        assert_eq!(config.monitor[0].labels, vec![String::from("Program Files"), String::from("windows")]);
+        assert_eq!(config.monitor[1].path, String::from("C:\\Users\\"));
+        assert_eq!(config.monitor[1].labels, vec![String::from("Users"), String::from("windows")]);
+    }
+
+    // ------------------------------------------------------------------------
 
-        assert_eq!(yaml[0]["nodename"].as_str().unwrap(), "FIM");
-        assert_eq!(yaml[0]["events"]["destination"].as_str().unwrap(), "file");
-        assert_eq!(yaml[0]["events"]["file"].as_str().unwrap(), "/var/lib/fim/events.json");
+    #[test]
+    fn test_load_not_found() {
+        let error = Config::load("not_found", "linux").err().unwrap();
+        assert_eq!(error.path, String::from("not_found"));
+        assert_eq!(error.issues.len(), 1);
+        assert_eq!(error.issues[0].line, None);
+        assert!(error.issues[0].message.starts_with("Unable to read file"));
+    }
 
-        assert_eq!(yaml[0]["monitor"][0]["path"].as_str().unwrap(), "/tmp/");
-        assert_eq!(yaml[0]["monitor"][1]["path"].as_str().unwrap(), "/bin/");
-        assert_eq!(yaml[0]["monitor"][2]["path"].as_str().unwrap(), "/usr/bin/");
-        assert_eq!(yaml[0]["monitor"][2]["labels"][0].as_str().unwrap(), "usr/bin");
-        assert_eq!(yaml[0]["monitor"][2]["labels"][1].as_str().unwrap(), "linux");
-        assert_eq!(yaml[0]["monitor"][3]["path"].as_str().unwrap(), "/etc");
-        assert_eq!(yaml[0]["monitor"][3]["labels"][0].as_str().unwrap(), "etc");
-        assert_eq!(yaml[0]["monitor"][3]["labels"][1].as_str().unwrap(), "linux");
+    // ------------------------------------------------------------------------
 
-        assert_eq!(yaml[0]["log"]["file"].as_str().unwrap(), "/var/log/fim/fim.log");
-        assert_eq!(yaml[0]["log"]["level"].as_str().unwrap(), "info");
+    #[test]
+    fn test_load_not_config() {
+        let error = Config::load("README.md", "linux").err().unwrap();
+        assert!(!error.issues.is_empty());
     }
 
     // ------------------------------------------------------------------------
 
     #[test]
-    fn test_read_config_windows() {
-        let yaml = read_config(String::from("config/windows/config.yml"));
+    fn test_load_errors() {
+        let path = "./test_load_errors.yml";
+        fs::write(path, "nodename: FIM
+events:
+  destination: network
+  color: blue
+  endpoint:
+    address: \"https://127.0.0.1:9200\"
+  retry:
+    max_attempts: 0
+    statuses: [503, 42]
+monitor:
+  - path: /tmp/
+    labels: tmp
+  - path: /etc
+    algorithms: [\"sha256\", \"crc32\"]
+    ignore: [glob: \"[\"]
+  - labels: [\"bin\"]
+  - path: /bin
+    scan:
+      schedule: \"0 3 * *\"
+scan:
+  interval: soon
+log:
+  level: verbose
+extra: true
+").unwrap();
+        let error = Config::load(path, "linux").err().unwrap();
+        fs::remove_file(path).unwrap();
+
+        let issues: Vec<(Option<usize>, &str)> = error.issues.iter()
+            .map(|issue| (issue.line, issue.key.as_str())).collect();
+        assert_eq!(issues, vec![
+            (Some(24), "extra"),
+            (Some(4), "events.color"),
+            (Some(21), "scan.interval"),
+            (Some(12), "monitor[0].labels"),
+            (Some(15), "monitor[1].ignore[0]"),
+            (Some(16), "monitor[2]"),
+            (Some(5), "events.endpoint.credentials.user"),
+            (Some(5), "events.endpoint.credentials.password"),
+            (Some(8), "events.retry.max_attempts"),
+            (Some(9), "events.retry.statuses"),
+            (Some(19), "monitor[3].scan.schedule"),
+            (Some(22), "log.file"),
+            (Some(23), "log.level")
+        ]);
+        assert_eq!(error.issues[9].message, String::from("invalid HTTP status '42'"));
+        assert_eq!(error.issues[1].message, String::from("unknown key"));
+        assert!(error.issues[5].message.contains("missing field `path`"));
+        assert!(error.to_string().starts_with("Invalid configuration file './test_load_errors.yml':\n  line 24, extra: unknown key"));
+    }
 
-        assert_eq!(yaml[0]["nodename"].as_str().unwrap(), "FIM");
-        assert_eq!(yaml[0]["events"]["destination"].as_str().unwrap(), "file");
-        assert_eq!(yaml[0]["events"]["file"].as_str().unwrap(), "C:\\ProgramData\\fim\\events.json");
+    // ------------------------------------------------------------------------
 
-        assert_eq!(yaml[0]["monitor"][0]["path"].as_str().unwrap(), "C:\\Program Files\\");
-        assert_eq!(yaml[0]["monitor"][0]["labels"][0].as_str().unwrap(), "Program Files");
-        assert_eq!(yaml[0]["monitor"][0]["labels"][1].as_str().unwrap(), "windows");
-        assert_eq!(yaml[0]["monitor"][1]["path"].as_str().unwrap(), "C:\\Users\\");
-        assert_eq!(yaml[0]["monitor"][1]["labels"][0].as_str().unwrap(), "Users");
-        assert_eq!(yaml[0]["monitor"][1]["labels"][1].as_str().unwrap(), "windows");
+    #[test]
+    fn test_load_outputs() {
+        let path = "./test_load_outputs.yml";
+        fs::write(path, "events:
+  outputs:
+    - type: file
+      file: /tmp/events.json
+      filter:
+        kinds: [CREATE, REMOVE]
+    - name: siem
+      type: elasticsearch
+      address: \"https://127.0.0.1:9200\"
+      insecure: true
+      schema: ecs
+      credentials:
+        user: admin
+        password: secret
+      retry:
+        max_attempts: 2
+      batch:
+        max_events: 10
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let config = Config::load(path, "linux").ok().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(config.outputs.len(), 2);
+        assert_eq!(config.outputs[0].name, "file");
+        assert_eq!(config.outputs[0].file, "/tmp/events.json");
+        assert_eq!(config.outputs[0].kinds, vec!["CREATE", "REMOVE"]);
+        assert_eq!(config.outputs[1].name, "siem");
+        assert_eq!(config.outputs[1].address, "https://127.0.0.1:9200");
+        assert_eq!(config.outputs[1].user, "admin");
+        assert!(config.outputs[1].insecure);
+        assert_eq!(config.outputs[0].schema, "fim");
+        assert_eq!(config.outputs[1].schema, "ecs");
+        assert_eq!(config.outputs[1].retry.max_attempts, Some(2));
+        assert_eq!(config.outputs[1].retry.base_delay, None);
+        assert_eq!(config.get_batch(&config.outputs[0]), (BATCH_MAX_EVENTS, BATCH_MAX_BYTES));
+        assert_eq!(config.get_batch(&config.outputs[1]), (10, BATCH_MAX_BYTES));
+        assert_eq!(config.get_outputs(), config.outputs);
+
+        fs::write(path, "events:
+  outputs:
+    - type: kafka
+    - name: audit
+      type: file
+      format: xml
+    - name: audit
+      type: file
+      file: /tmp/audit.json
+      retry:
+        max_attempts: 0
+        statuses: [700]
+      batch:
+        max_events: 0
+    - type: elasticsearch
+      address: \"https://127.0.0.1:9200\"
+    - name: backup
+      type: elasticsearch
+      format: leef
+      schema: ecs
+      address: \"https://127.0.0.1:9201\"
+      credentials:
+        user: admin
+        password: secret
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let error = Config::load(path, "linux").err().unwrap();
+        fs::remove_file(path).unwrap();
+        let issues: Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+        assert_eq!(issues, vec![
+            "events.outputs[0].type",
+            "events.outputs[1].format",
+            "events.outputs[1]",
+            "events.outputs[2].name",
+            "events.outputs[2].retry.max_attempts",
+            "events.outputs[2].retry.statuses",
+            "events.outputs[2].batch.max_events",
+            "events.outputs[3]",
+            "events.outputs[3]",
+            "events.outputs[4].schema",
+            "events.outputs[4].format"
+        ]);
+        assert_eq!(error.issues[0].message, String::from("invalid value 'kafka', available: file, elasticsearch, syslog, splunk"));
+        assert_eq!(error.issues[2].message, String::from("required key 'file' not found"));
+        assert_eq!(error.issues[3].message, String::from("duplicated output name 'audit'"));
+        assert_eq!(error.issues[5].message, String::from("invalid HTTP status '700'"));
+        assert_eq!(error.issues[9].message, String::from("only json format supports the ecs schema"));
+        assert_eq!(error.issues[10].message, String::from("only json format is supported by elasticsearch outputs"));
+    }
+
+    // ------------------------------------------------------------------------
 
-        assert_eq!(yaml[0]["log"]["file"].as_str().unwrap(), "C:\\ProgramData\\fim\\fim.log");
-        assert_eq!(yaml[0]["log"]["level"].as_str().unwrap(), "info");
+    #[test]
+    fn test_load_syslog_outputs() {
+        let path = "./test_load_syslog_outputs.yml";
+        fs::write(path, "events:
+  outputs:
+    - name: local
+      type: syslog
+      protocol: unix
+    - name: remote
+      type: syslog
+      protocol: tls
+      address: \"logs.example.com:6514\"
+      rfc: 3164
+      facility: authpriv
+      ca: /etc/fim/ca.pem
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let config = Config::load(path, "linux").ok().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(config.outputs[0].address, "/dev/log");
+        assert_eq!(config.outputs[0].rfc, 5424);
+        assert_eq!(config.outputs[0].facility, "local0");
+        assert_eq!(config.outputs[1].protocol, "tls");
+        assert_eq!(config.outputs[1].address, "logs.example.com:6514");
+        assert_eq!(config.outputs[1].rfc, 3164);
+        assert_eq!(config.outputs[1].facility, "authpriv");
+        assert_eq!(config.outputs[1].ca, "/etc/fim/ca.pem");
+
+        fs::write(path, "events:
+  outputs:
+    - type: syslog
+      protocol: sctp
+      address: \"127.0.0.1:514\"
+      rfc: 5425
+      facility: web
+    - type: syslog
+      name: ecs
+      schema: ecs
+      address: \"127.0.0.1:514\"
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let error = Config::load(path, "linux").err().unwrap();
+        fs::remove_file(path).unwrap();
+        let issues: Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+        assert_eq!(issues, vec!["events.outputs[0].protocol", "events.outputs[0].rfc", "events.outputs[0].facility", "events.outputs[1].schema"]);
+        assert_eq!(error.issues[1].message, String::from("invalid value '5425', available: 5424, 3164"));
+        assert_eq!(error.issues[3].message, String::from("only file and elasticsearch outputs support the ecs schema"));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_load_splunk_outputs() {
+        let path = "./test_load_splunk_outputs.yml";
+        fs::write(path, "events:
+  outputs:
+    - name: hec
+      type: splunk
+      address: \"https://splunk.example.com:8088\"
+      token: 11111111-2222-3333-4444-555555555555
+    - name: hec_ack
+      type: splunk
+      address: \"https://splunk.example.com:8088\"
+      token: 11111111-2222-3333-4444-555555555555
+      format: cef
+      index: security
+      source: web01
+      channel: 0f4d9a5e-6a8b-4d3c-9d8e-2b1a7c6f5e4d
+      insecure: true
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let config = Config::load(path, "linux").ok().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(config.outputs[0].token, "11111111-2222-3333-4444-555555555555");
+        assert_eq!(config.outputs[0].index, "");
+        assert_eq!(config.outputs[0].sourcetype, "_json");
+        assert_eq!(config.outputs[0].source, "fim");
+        assert_eq!(config.outputs[0].channel, "");
+        assert_eq!(config.outputs[1].index, "security");
+        assert_eq!(config.outputs[1].sourcetype, "cef");
+        assert_eq!(config.outputs[1].source, "web01");
+        assert_eq!(config.outputs[1].channel, "0f4d9a5e-6a8b-4d3c-9d8e-2b1a7c6f5e4d");
+        assert!(config.outputs[1].insecure);
+
+        fs::write(path, "events:
+  outputs:
+    - type: splunk
+      address: \"https://splunk.example.com:8088\"
+      channel: fim
+    - type: splunk
+      name: ecs
+      schema: ecs
+      address: \"https://splunk.example.com:8088\"
+      token: 11111111-2222-3333-4444-555555555555
+monitor:
+  - path: /tmp/
+log:
+  file: /tmp/fim.log
+").unwrap();
+        let error = Config::load(path, "linux").err().unwrap();
+        fs::remove_file(path).unwrap();
+        let issues: Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+        assert_eq!(issues, vec!["events.outputs[0]", "events.outputs[0].channel", "events.outputs[1].schema"]);
+        assert_eq!(error.issues[1].message, String::from("invalid value 'fim', a GUID is required"));
+        assert_eq!(error.issues[2].message, String::from("only file and elasticsearch outputs support the ecs schema"));
     }
 
     // ------------------------------------------------------------------------
 
     #[test]
-    #[should_panic(expected = "NotFound")]
-    fn test_read_config_panic() {
-        read_config(String::from("not_found"));
+    fn test_load_syntax_error() {
+        let path = "./test_load_syntax_error.yml";
+        fs::write(path, "nodename: FIM\nmonitor:\n  - path: [/tmp\n").unwrap();
+        let error = Config::load(path, "linux").err().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(error.issues.len(), 1);
+        assert!(error.issues[0].line.is_some());
     }
 
     // ------------------------------------------------------------------------
 
     #[test]
-    #[should_panic(expected = "ScanError")]
-    fn test_read_config_panic_not_config() {
-        read_config(String::from("README.md"));
+    fn test_line_index() {
+        let index = LineIndex::new("a: 1\nb:\n  c: [1, 2]\n  d:\n    - e: x\n      f: y\n    - g\n");
+        assert_eq!(index.get_line("a"), Some(1));
+        assert_eq!(index.get_line("b"), Some(2));
+        assert_eq!(index.get_line("b.c"), Some(3));
+        assert_eq!(index.get_line("b.c[1]"), Some(3));
+        assert_eq!(index.get_line("b.d[0].e"), Some(5));
+        assert_eq!(index.get_line("b.d[0].f"), Some(6));
+        assert_eq!(index.get_line("b.d[1]"), Some(7));
+        assert_eq!(index.get_line("b.d[1].missing"), Some(7));
+        assert_eq!(index.get_line("missing"), None);
     }
 
     // ------------------------------------------------------------------------
@@ -0,0 +1,235 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle JSON objects
+use serde_json::{json, to_string, Map, Value};
+// To split file paths
+use std::path::Path;
+// To format file dates
+use time::OffsetDateTime;
+
+use crate::format;
+use crate::output::Document;
+
+const ECS_VERSION: &str = "8.11.0";
+
+// ----------------------------------------------------------------------------
+
+// Get the ECS event types of the kind of event
+fn get_types(kind: &str) -> Vec<&'static str> {
+    match kind {
+        "CREATE" => vec!["creation"],
+        "REMOVE" => vec!["deletion"],
+        "WRITE" | "CLOSE_WRITE" | "RENAME" | "CHMOD" => vec!["change"],
+        _ => vec!["info"]
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// File dates are stored in seconds since epoch, 0 means the date is not known
+fn get_date(value: Value) -> Value {
+    match value.as_i64().filter(|seconds| *seconds != 0).and_then(|seconds| OffsetDateTime::from_unix_timestamp(seconds).ok()) {
+        Some(time) => json!(format::format_time(time)),
+        None => Value::Null
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// ECS stores identifiers as keywords
+fn get_keyword(value: Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        value => json!(format::get_text(&value))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Remove empty values so documents only have the fields known for the event
+fn prune(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .map(|(key, value)| (key, prune(value)))
+            .filter(|(_, value)| ! value.is_null() && value != &json!({}))
+            .collect()),
+        value => value
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Map the event to Elastic Common Schema, fields without an ECS equivalent are kept under fim
+pub fn get_json(document: &dyn Document) -> Value {
+    let original = document.get_json();
+    let mut fields = match original.clone() {
+        Value::Object(map) => map,
+        _ => Map::new()
+    };
+    let mut take = |name: &str| fields.remove(name).unwrap_or(Value::Null);
+    let kind = format::get_text(&take("kind"));
+    take("timestamp");
+    take("message");
+    let file = take("file");
+
+    let mut ecs = json!({
+        "@timestamp": format::format_time(format::get_time(&original)),
+        "message": format::get_message(&original),
+        "tags": take("labels"),
+        "event": {
+            "id": document.get_id(),
+            "kind": "event",
+            "category": [if file.is_string() { "file" } else { "host" }],
+            "type": get_types(&kind),
+            "action": kind.to_lowercase(),
+            "module": "fim",
+            "dataset": "fim.events",
+            "severity": format::get_severity(&kind)
+        },
+        "host": { "name": take("hostname"), "os": { "type": take("system") } },
+        "agent": { "type": "fim", "name": take("node"), "version": take("version") },
+        "ecs": { "version": ECS_VERSION }
+    });
+    if let Some(path) = file.as_str() {
+        let path = Path::new(path);
+        ecs["file"] = json!({
+            "path": file,
+            "name": path.file_name().map(|name| name.to_string_lossy()),
+            "directory": path.parent().map(|parent| parent.to_string_lossy()),
+            "hash": take("checksums"),
+            "size": take("size"),
+            "mode": take("mode"),
+            "uid": get_keyword(take("uid")),
+            "gid": get_keyword(take("gid")),
+            "owner": take("user"),
+            "group": take("group"),
+            "inode": get_keyword(take("inode")),
+            "device": get_keyword(take("device")),
+            "accessed": get_date(take("atime")),
+            "mtime": get_date(take("mtime")),
+            "ctime": get_date(take("ctime"))
+        });
+    }
+    fields.insert(String::from("kind"), json!(kind));
+    ecs["fim"] = Value::Object(fields);
+    prune(ecs)
+}
+
+// ----------------------------------------------------------------------------
+
+// Event written with the ECS schema
+pub struct EcsDocument<'a> {
+    document: &'a dyn Document
+}
+
+impl<'a> EcsDocument<'a> {
+    pub fn new(document: &'a dyn Document) -> Self {
+        EcsDocument { document }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Document for EcsDocument<'_> {
+    fn get_id(&self) -> String {
+        self.document.get_id()
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_json(&self) -> Value {
+        get_json(self.document)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Identifier is stored in event.id
+    fn format_json(&self) -> String {
+        to_string(&self.get_json()).unwrap()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::TestDocument;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_json() {
+        let document = TestDocument::new("1234", json!({
+            "timestamp": "1700000000123",
+            "hostname": "web01",
+            "node": "FIM",
+            "pid": 42,
+            "version": "0.3.2",
+            "labels": ["etc"],
+            "kind": "WRITE",
+            "file": "/etc/hosts",
+            "checksum": "abc",
+            "checksums": { "md5": "def", "sha256": "ghi" },
+            "checksum_before": "xyz",
+            "content_changed": true,
+            "size": 120,
+            "mode": "0644",
+            "uid": 0,
+            "gid": 0,
+            "user": "root",
+            "group": "root",
+            "inode": 55,
+            "mtime": 1700000000,
+            "atime": 0,
+            "ctime": 0,
+            "system": "linux"
+        }));
+        assert_eq!(get_json(&document), json!({
+            "@timestamp": "2023-11-14T22:13:20.123Z",
+            "message": "WRITE /etc/hosts",
+            "tags": ["etc"],
+            "event": {
+                "id": "1234", "kind": "event", "category": ["file"], "type": ["change"], "action": "write",
+                "module": "fim", "dataset": "fim.events", "severity": 5
+            },
+            "host": { "name": "web01", "os": { "type": "linux" } },
+            "agent": { "type": "fim", "name": "FIM", "version": "0.3.2" },
+            "ecs": { "version": ECS_VERSION },
+            "file": {
+                "path": "/etc/hosts", "name": "hosts", "directory": "/etc",
+                "hash": { "md5": "def", "sha256": "ghi" },
+                "size": 120, "mode": "0644", "uid": "0", "gid": "0", "owner": "root", "group": "root",
+                "inode": "55", "mtime": "2023-11-14T22:13:20.000Z"
+            },
+            "fim": { "kind": "WRITE", "pid": 42, "checksum": "abc", "checksum_before": "xyz", "content_changed": true }
+        }));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_json_agent_event() {
+        let document = TestDocument::new("1234", json!({ "kind": "HEARTBEAT", "message": "Agent alive", "uptime": 60, "last_error": null }));
+        let json = get_json(&document);
+        assert_eq!(json["event"]["category"], json!(["host"]));
+        assert_eq!(json["event"]["type"], json!(["info"]));
+        assert_eq!(json["event"]["action"], "heartbeat");
+        assert_eq!(json["message"], "Agent alive");
+        assert_eq!(json["fim"], json!({ "kind": "HEARTBEAT", "uptime": 60 }));
+        assert!(json.get("file").is_none());
+        assert!(json.get("tags").is_none());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_json() {
+        let document = TestDocument::new("1234", json!({ "kind": "CREATE", "file": "/tmp/test" }));
+        let ecs = EcsDocument::new(&document);
+        assert_eq!(ecs.get_id(), "1234");
+        let json: Value = serde_json::from_str(&ecs.format_json()).unwrap();
+        assert_eq!(json["event"]["id"], "1234");
+        assert!(json.get("id").is_none());
+    }
+}
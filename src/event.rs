@@ -4,7 +4,7 @@
 use std::fmt;
 // To handle files
 use std::fs::OpenOptions;
-use std::io::{Write, Error, ErrorKind};
+use std::io::{Write, Error};
 // Handle time intervals
 use std::time::Duration;
 // Event handling
@@ -12,11 +12,20 @@ use notify::op::Op;
 // To log the program procedure
 use log::*;
 // To handle JSON objects
-use serde_json::{json, to_string};
+use serde_json::{json, Value};
 // To manage Pathbufs
 use std::path::PathBuf;
+// To store checksums sorted by algorithm
+use std::collections::BTreeMap;
 // To manage HTTP requests
-use reqwest::Client;
+use reqwest::{Client, StatusCode, header};
+// To track if the endpoint is reachable between requests
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::metadata::Metadata;
+use crate::backoff;
+use crate::output::Document;
 
 pub struct Event {
     pub id: String,
@@ -29,83 +38,75 @@ pub struct Event {
     pub labels: Vec<String>,
     pub kind: String,
     pub checksum: String,
+    pub checksums: BTreeMap<String, String>,
+    pub checksum_before: String,
+    pub checksum_after: String,
+    pub content_changed: bool,
+    pub metadata: Option<Metadata>,
+    pub metadata_before: Option<Metadata>,
+    pub old_path: Option<PathBuf>,
+    pub new_path: Option<PathBuf>,
+    pub moved: Option<String>,
     pub pid: u32,
     pub system: String
 }
 
-impl Event {
-    // Get formatted string with all required data
-    fn format_json(&self) -> String {
-        let obj = json!({
-            "id": self.id.clone(),
-            "timestamp": self.timestamp.clone(),
-            "hostname": self.hostname.clone(),
-            "node": self.nodename.clone(),
-            "pid": self.pid.clone(),
-            "version": self.version.clone(),
-            "labels": self.labels.clone(),
-            "kind": self.kind.clone(),
-            "file": String::from(self.path.clone().to_str().unwrap()),
-            "checksum": self.checksum.clone(),
-            "system": self.system.clone()
-        });
-        to_string(&obj).unwrap()
-    }
-
-    // ------------------------------------------------------------------------
-
-    // Function to write the received events to file
-    pub fn log_event(&self, file: String){
-        let mut events_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(true)
-            .open(file)
-            .expect("(log_event) Unable to open events log file.");
+// ----------------------------------------------------------------------------
 
-        match self.operation {
-            Op::CREATE|Op::WRITE|Op::RENAME|Op::REMOVE|Op::CHMOD|Op::CLOSE_WRITE|Op::RESCAN => {
-                writeln!(events_file, "{}", self.format_json() )
-            },
-            _ => {
-                let error_msg = "Event Op not Handled or do not exists";
-                error!("{}", error_msg);
-                Err(Error::new(ErrorKind::InvalidInput, error_msg))
-            },
-        }.expect("(log_event) Error writing event")
+impl Document for Event {
+    fn get_id(&self) -> String {
+        self.id.clone()
     }
 
     // ------------------------------------------------------------------------
 
-    // Function to send events through network
-    pub async fn send(&self, index: String, address: String, user: String, pass: String, insecure: bool) {
-        let data = json!({
+    // Get JSON object with all event data except the identifier
+    fn get_json(&self) -> Value {
+        let mut obj = json!({
             "timestamp": self.timestamp.clone(),
             "hostname": self.hostname.clone(),
             "node": self.nodename.clone(),
-            "pid": self.pid.clone(),
+            "pid": self.pid,
             "version": self.version.clone(),
             "labels": self.labels.clone(),
             "kind": self.kind.clone(),
             "file": String::from(self.path.clone().to_str().unwrap()),
             "checksum": self.checksum.clone(),
+            "checksums": self.checksums.clone(),
+            "checksum_before": self.checksum_before.clone(),
+            "checksum_after": self.checksum_after.clone(),
+            "content_changed": self.content_changed,
             "system": self.system.clone()
         });
 
-        let request_url = format!("{}/{}/_doc/{}", address, index, self.id);
-        let client = Client::builder()
-            .danger_accept_invalid_certs(insecure)
-            .timeout(Duration::from_secs(30))
-            .build().unwrap();
-        match client
-            .post(request_url)
-            .basic_auth(user, Some(pass))
-            .json(&data)
-            .send()
-            .await{
-            Ok(response) => debug!("Response received: {:?}", response),
-            Err(e) => debug!("Error on request: {:?}", e)
-        };
+        if let Some(metadata) = &self.metadata {
+            if let Value::Object(map) = metadata.get_json() {
+                obj.as_object_mut().unwrap().extend(map);
+            }
+        }
+
+        // Renamed files report both paths, the one outside of monitored paths is unknown
+        if self.moved.is_some() {
+            obj["old_path"] = json!(self.old_path.as_ref().map(|path| path.to_str().unwrap()));
+            obj["new_path"] = json!(self.new_path.as_ref().map(|path| path.to_str().unwrap()));
+            obj["moved"] = json!(self.moved.clone());
+        }
+
+        // Attributes changed by the event are reported with their previous and current value
+        if let Some(before) = &self.metadata_before {
+            let before_json = before.get_json();
+            let after_json = match &self.metadata {
+                Some(metadata) => metadata.get_json(),
+                None => json!({})
+            };
+            for field in ["mode", "uid", "gid", "user", "group"] {
+                obj[format!("{}_before", field)] = before_json[field].clone();
+                if ! after_json[field].is_null() {
+                    obj[format!("{}_after", field)] = after_json[field].clone();
+                }
+            }
+        }
+        obj
     }
 }
 
@@ -123,6 +124,257 @@ impl fmt::Debug for Event {
 
 // ----------------------------------------------------------------------------
 
+// Function to append a formatted JSON line to the events file
+pub fn log_json(file: String, line: String) -> Result<(), Error> {
+    let mut events_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?;
+    // Written at once so a stopped process doesn't leave half lines
+    events_file.write_all(format!("{}\n", line).as_bytes())
+}
+
+// ----------------------------------------------------------------------------
+
+// State of an events endpoint after the last request
+pub struct Reachability {
+    reachable: AtomicBool,
+    // Error that made the endpoint unreachable, not reported yet
+    error: Mutex<Option<String>>
+}
+
+impl Reachability {
+    const fn new() -> Self {
+        Reachability { reachable: AtomicBool::new(true), error: Mutex::new(None) }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Only changes of the endpoint state are logged
+    fn set(&self, result: Result<(), String>) {
+        match result {
+            Ok(_) => if ! self.reachable.swap(true, Ordering::Relaxed) {
+                info!("Events endpoint is reachable again");
+            },
+            Err(e) => if self.reachable.swap(false, Ordering::Relaxed) {
+                error!("Events endpoint unreachable, error: {}", e);
+                *self.error.lock().unwrap() = Some(e);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn is_reachable(&self) -> bool {
+        self.reachable.load(Ordering::Relaxed)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the error that made the endpoint unreachable if it wasn't already taken
+    pub fn take_error(&self) -> Option<String> {
+        self.error.lock().unwrap().take()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Result of a request, failed ones could be delivered later
+pub enum Delivery {
+    Delivered,
+    // The endpoint refused the document itself, sending it again won't work
+    Rejected(String),
+    Failed(String)
+}
+
+// ----------------------------------------------------------------------------
+
+// Clients keep their connections open, they are shared by every endpoint request
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static INSECURE_CLIENT: OnceLock<Client> = OnceLock::new();
+
+pub fn get_client(insecure: bool) -> Client {
+    let client = match insecure {
+        true => &INSECURE_CLIENT,
+        false => &CLIENT
+    };
+    client.get_or_init(|| Client::builder()
+        .danger_accept_invalid_certs(insecure)
+        .timeout(Duration::from_secs(30))
+        .build().unwrap()).clone()
+}
+
+// ----------------------------------------------------------------------------
+
+// Events endpoint of an output, each one has its own retry policy and state
+pub struct Endpoint {
+    pub address: String,
+    pub user: String,
+    pub pass: String,
+    pub insecure: bool,
+    policy: Mutex<backoff::Policy>,
+    pub reachability: Reachability
+}
+
+impl Endpoint {
+    pub fn new(address: &str, user: &str, pass: &str, insecure: bool, policy: backoff::Policy) -> Self {
+        Endpoint {
+            address: String::from(address),
+            user: String::from(user),
+            pass: String::from(pass),
+            insecure,
+            policy: Mutex::new(policy),
+            reachability: Reachability::new()
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn set_policy(&self, policy: backoff::Policy) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Retries are not done while the endpoint is unreachable
+    pub fn get_policy(&self) -> backoff::Policy {
+        let mut policy = self.policy.lock().unwrap().clone();
+        if ! self.reachability.is_reachable() {
+            policy.max_attempts = 1;
+        }
+        policy
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Failed requests are retried with the configured policy while the endpoint is reachable,
+    // otherwise the spool keeps the event until the next delivery attempt
+    pub async fn post_json(&self, data: &Value, request_url: &str) -> Delivery {
+        let client = get_client(self.insecure);
+        let policy = self.get_policy();
+        match backoff::send(&policy, "Event request", || client
+            .post(request_url)
+            .basic_auth(&self.user, Some(&self.pass))
+            .json(data)
+            .send()
+        ).await{
+            Ok(response) => {
+                debug!("Response received: {:?}", response);
+                let status = response.status();
+                self.reachability.set(get_reachability(status, &policy));
+                get_delivery(status, &policy, format!("HTTP status {}", status))
+            },
+            Err(e) => {
+                debug!("Error on request: {:?}", e);
+                self.reachability.set(Err(e.to_string()));
+                Delivery::Failed(e.to_string())
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Send documents of the given indexes and ids in a single bulk request to the given address,
+    // the delivery of each document is returned in the same order
+    pub async fn post_bulk(&self, address: &str, documents: &[(String, String, Value)]) -> Vec<Delivery> {
+        let mut body = String::new();
+        for (index, id, data) in documents {
+            body.push_str(&format!("{}\n{}\n", json!({ "index": { "_index": index, "_id": id } }), data));
+        }
+        let client = get_client(self.insecure);
+        let policy = self.get_policy();
+        let request_url = format!("{}/_bulk", address);
+        let result = backoff::send(&policy, "Bulk request", || client
+            .post(&request_url)
+            .basic_auth(&self.user, Some(&self.pass))
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .body(body.clone())
+            .send()
+        ).await;
+
+        // Failed documents of an accepted request don't make the endpoint unreachable
+        let (deliveries, reachable) = match result {
+            Ok(response) if response.status().is_success() => {
+                let items = match response.json::<Value>().await {
+                    Ok(mut value) => value["items"].take(),
+                    Err(e) => json!(e.to_string())
+                };
+                ((0..documents.len()).map(|position| get_item_delivery(&items[position], &policy)).collect(), Ok(()))
+            },
+            Ok(response) => {
+                let status = response.status();
+                let error = format!("HTTP status {}", status);
+                (documents.iter().map(|_| get_delivery(status, &policy, error.clone())).collect(), get_reachability(status, &policy))
+            },
+            Err(e) => {
+                debug!("Error on bulk request: {:?}", e);
+                (documents.iter().map(|_| Delivery::Failed(e.to_string())).collect(), Err(e.to_string()))
+            }
+        };
+        self.reachability.set(reachable);
+        deliveries
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Server errors and responses of a wrong address or credentials make the endpoint
+// unreachable until it is fixed
+fn get_reachability(status: StatusCode, policy: &backoff::Policy) -> Result<(), String> {
+    match status.is_server_error() || policy.is_retryable(status) || is_misconfigured(status) {
+        true => Err(format!("HTTP status {}", status)),
+        false => Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Statuses of a wrong address or credentials
+fn is_misconfigured(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 401 | 403 | 404)
+}
+
+// ----------------------------------------------------------------------------
+
+// Server errors and documents refused because of the endpoint settings are kept as failed
+// so they are delivered once the endpoint is fixed, even if the status is not retried
+pub fn get_delivery(status: StatusCode, policy: &backoff::Policy, error: String) -> Delivery {
+    if status.is_success() {
+        Delivery::Delivered
+    }else if status.is_server_error() || policy.is_retryable(status) || is_misconfigured(status) {
+        Delivery::Failed(error)
+    }else{
+        Delivery::Rejected(error)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the endpoint address, the index and the document id of a document URL
+pub fn split_url(request_url: &str) -> Option<(String, String, String)> {
+    let (path, id) = request_url.rsplit_once("/_doc/")?;
+    let (address, index) = path.rsplit_once('/')?;
+    Some((String::from(address), String::from(index), String::from(id)))
+}
+
+// ----------------------------------------------------------------------------
+
+// Each item of a bulk response has the status of one document under its action name
+fn get_item_delivery(item: &Value, policy: &backoff::Policy) -> Delivery {
+    let result = match item.as_object().and_then(|item| item.values().next()) {
+        Some(result) => result,
+        None => return Delivery::Failed(String::from("document result not found in bulk response"))
+    };
+    let status = result["status"].as_u64().unwrap_or(0) as u16;
+    let error = format!("HTTP status {}, error: {}", status, result["error"]);
+    match StatusCode::from_u16(status) {
+        Ok(status) => get_delivery(status, policy, error),
+        Err(_) => Delivery::Failed(error)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
 pub fn get_kind(operation: Op) -> String {
     match operation {
         Op::CREATE => { String::from("CREATE") },
@@ -164,6 +416,15 @@ mod tests {
             labels: Vec::new(),
             kind: "TEST".to_string(),
             checksum: "UNKNOWN".to_string(),
+            checksums: BTreeMap::from([("md5".to_string(), "UNKNOWN".to_string())]),
+            checksum_before: "UNKNOWN".to_string(),
+            checksum_after: "UNKNOWN".to_string(),
+            content_changed: false,
+            metadata: None,
+            metadata_before: None,
+            old_path: None,
+            new_path: None,
+            moved: None,
             pid: 0,
             system: "test".to_string()
         }
@@ -183,22 +444,18 @@ mod tests {
         assert_eq!(evt.path, PathBuf::new());
         assert_eq!(evt.labels, Vec::<String>::new());
         assert_eq!(evt.kind, String::from("TEST"));
+        assert_eq!(evt.checksums["md5"], String::from("UNKNOWN"));
+        assert_eq!(evt.checksum_before, String::from("UNKNOWN"));
+        assert_eq!(evt.checksum_after, String::from("UNKNOWN"));
+        assert!(!evt.content_changed);
+        assert_eq!(evt.metadata, None);
+        assert_eq!(evt.metadata_before, None);
         assert_eq!(evt.pid, 0);
         assert_eq!(evt.system, String::from("test"));
     }
 
     // ------------------------------------------------------------------------
 
-    #[test]
-    fn test_send_event() {
-        let evt = create_test_event();
-        tokio_test::block_on( evt.send(
-            String::from("test"), String::from("https://127.0.0.1:9200"),
-            String::from("admin"), String::from("admin"), true) );
-    }
-
-    // ------------------------------------------------------------------------
-
     #[test]
     fn test_get_kind(){
         assert_eq!(get_kind(Op::CREATE), String::from("CREATE"));
@@ -223,21 +480,167 @@ mod tests {
 
     #[test]
     fn test_format_json() {
-        let expected = "{\"checksum\":\"UNKNOWN\",\"file\":\"\",\"hostname\":\"Hostname\",\"id\":\"Test_id\",\"kind\":\"TEST\",\"labels\":[],\"node\":\"FIM\",\"pid\":0,\"system\":\"test\",\"timestamp\":\"Timestamp\",\"version\":\"x.x.x\"}";
+        let expected = "{\"checksum\":\"UNKNOWN\",\"checksum_after\":\"UNKNOWN\",\"checksum_before\":\"UNKNOWN\",\"checksums\":{\"md5\":\"UNKNOWN\"},\"content_changed\":false,\"file\":\"\",\"hostname\":\"Hostname\",\"id\":\"Test_id\",\"kind\":\"TEST\",\"labels\":[],\"node\":\"FIM\",\"pid\":0,\"system\":\"test\",\"timestamp\":\"Timestamp\",\"version\":\"x.x.x\"}";
         assert_eq!(create_test_event().format_json(), expected);
     }
 
     // ------------------------------------------------------------------------
 
+    #[test]
+    fn test_get_json_metadata() {
+        let mut evt = create_test_event();
+        let metadata = Metadata {
+            size: 10, mode: 0o100600, uid: 1, gid: 1, inode: 2, device: 3,
+            nlink: 1, atime: 4, mtime: 5, ctime: 6
+        };
+        evt.metadata = Some(metadata.clone());
+        let json = evt.get_json();
+        assert_eq!(json["size"], 10);
+        assert_eq!(json["mode"], "0600");
+        assert_eq!(json["inode"], 2);
+        assert_eq!(json["mtime"], 5);
+        assert!(json["mode_before"].is_null());
+
+        let mut before = metadata.clone();
+        before.mode = 0o100644;
+        before.uid = 0;
+        evt.operation = Op::CHMOD;
+        evt.metadata_before = Some(before);
+        let json = evt.get_json();
+        assert_eq!(json["mode_before"], "0644");
+        assert_eq!(json["mode_after"], "0600");
+        assert_eq!(json["uid_before"], 0);
+        assert_eq!(json["uid_after"], 1);
+        assert_eq!(json["gid_before"], json["gid_after"]);
+        assert_eq!(json["user_before"], crate::metadata::get_user_name(0));
+
+        evt.metadata = None;
+        let json = evt.get_json();
+        assert_eq!(json["mode_before"], "0644");
+        assert!(json["mode_after"].is_null());
+        assert!(json["size"].is_null());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_json_rename() {
+        let mut evt = create_test_event();
+        assert!(evt.get_json()["old_path"].is_null());
+
+        evt.operation = Op::RENAME;
+        evt.old_path = Some(PathBuf::from("/tmp/old"));
+        evt.new_path = Some(PathBuf::from("/tmp/new"));
+        evt.moved = Some(String::from("within"));
+        let json = evt.get_json();
+        assert_eq!(json["old_path"], "/tmp/old");
+        assert_eq!(json["new_path"], "/tmp/new");
+        assert_eq!(json["moved"], "within");
+
+        evt.new_path = None;
+        let json = evt.get_json();
+        assert!(json.as_object().unwrap().contains_key("new_path"));
+        assert!(json["new_path"].is_null());
+
+        evt.old_path = None;
+        evt.new_path = Some(PathBuf::from("/tmp/new"));
+        evt.moved = Some(String::from("in"));
+        let json = evt.get_json();
+        assert!(json.as_object().unwrap().contains_key("old_path"));
+        assert!(json["old_path"].is_null());
+        assert_eq!(json["new_path"], "/tmp/new");
+    }
+
+    // ------------------------------------------------------------------------
+
     #[test]
     fn test_log_event() {
         let filename = String::from("test_event.json");
         let evt = create_test_event();
 
-        evt.log_event(filename.clone());
+        log_json(filename.clone(), evt.format_json()).unwrap();
         let contents = fs::read_to_string(filename.clone());
-        let expected = "{\"checksum\":\"UNKNOWN\",\"file\":\"\",\"hostname\":\"Hostname\",\"id\":\"Test_id\",\"kind\":\"TEST\",\"labels\":[],\"node\":\"FIM\",\"pid\":0,\"system\":\"test\",\"timestamp\":\"Timestamp\",\"version\":\"x.x.x\"}\n";
+        let expected = "{\"checksum\":\"UNKNOWN\",\"checksum_after\":\"UNKNOWN\",\"checksum_before\":\"UNKNOWN\",\"checksums\":{\"md5\":\"UNKNOWN\"},\"content_changed\":false,\"file\":\"\",\"hostname\":\"Hostname\",\"id\":\"Test_id\",\"kind\":\"TEST\",\"labels\":[],\"node\":\"FIM\",\"pid\":0,\"system\":\"test\",\"timestamp\":\"Timestamp\",\"version\":\"x.x.x\"}\n";
         assert_eq!(contents.unwrap(), expected);
         remove_test_file(filename.clone());
     }
-}
\ No newline at end of file
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_reachability() {
+        let endpoint = Reachability::new();
+        endpoint.set(Ok(()));
+        assert_eq!(endpoint.take_error(), None);
+        endpoint.set(Err(String::from("Connection refused")));
+        endpoint.set(Err(String::from("Timeout")));
+        assert!(!endpoint.is_reachable());
+        // Only the first error of each outage is reported
+        assert_eq!(endpoint.take_error(), Some(String::from("Connection refused")));
+        assert_eq!(endpoint.take_error(), None);
+        endpoint.set(Ok(()));
+        assert!(endpoint.is_reachable());
+        endpoint.set(Err(String::from("Timeout")));
+        assert_eq!(endpoint.take_error(), Some(String::from("Timeout")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_reachability() {
+        let policy = backoff::Policy { max_attempts: 1, base_delay: 0, jitter: false, statuses: vec![429, 503] };
+        assert_eq!(get_reachability(StatusCode::CREATED, &policy), Ok(()));
+        assert_eq!(get_reachability(StatusCode::BAD_REQUEST, &policy), Ok(()));
+        assert_eq!(get_reachability(StatusCode::TOO_MANY_REQUESTS, &policy), Err(String::from("HTTP status 429 Too Many Requests")));
+        assert_eq!(get_reachability(StatusCode::INTERNAL_SERVER_ERROR, &policy), Err(String::from("HTTP status 500 Internal Server Error")));
+        // Wrong credentials or address
+        assert!(get_reachability(StatusCode::UNAUTHORIZED, &policy).is_err());
+        assert!(get_reachability(StatusCode::FORBIDDEN, &policy).is_err());
+        assert!(get_reachability(StatusCode::NOT_FOUND, &policy).is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(split_url("https://127.0.0.1:9200/fim-2022.01.01/_doc/1234"), Some((
+            String::from("https://127.0.0.1:9200"), String::from("fim-2022.01.01"), String::from("1234")
+        )));
+        assert_eq!(split_url("https://127.0.0.1:9200/_bulk"), None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_post_bulk() {
+        let (address, server) = crate::mock::start_server(vec![501, 400]);
+        let policy = backoff::Policy { max_attempts: 3, base_delay: 0, jitter: false, statuses: vec![429, 503] };
+        let endpoint = Endpoint::new(&address, "admin", "admin", false, policy);
+        let documents = vec![(String::from("fim"), String::from("1"), json!({"kind": "CREATE"}))];
+        // Server errors are kept even if their status is not retried
+        assert!(matches!(endpoint.post_bulk(&address, &documents).await[..], [Delivery::Failed(_)]));
+        assert!(!endpoint.reachability.is_reachable());
+        assert_eq!(endpoint.get_policy().max_attempts, 1);
+        assert!(matches!(endpoint.post_bulk(&address, &documents).await[..], [Delivery::Rejected(_)]));
+        assert!(endpoint.reachability.is_reachable());
+        assert_eq!(endpoint.get_policy().max_attempts, 3);
+        server.join().unwrap();
+        assert!(matches!(endpoint.post_bulk(&address, &documents).await[..], [Delivery::Failed(_)]));
+        assert!(endpoint.reachability.take_error().is_some());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_item_delivery() {
+        let policy = backoff::Policy { max_attempts: 1, base_delay: 0, jitter: false, statuses: vec![429, 503] };
+        assert!(matches!(get_item_delivery(&json!({"index": {"status": 201}}), &policy), Delivery::Delivered));
+        assert!(matches!(get_item_delivery(&json!({"index": {"status": 429}}), &policy), Delivery::Failed(_)));
+        assert!(matches!(get_item_delivery(&json!({"index": {"status": 500}}), &policy), Delivery::Failed(_)));
+        assert!(matches!(get_item_delivery(&json!({"index": {"status": 403}}), &policy), Delivery::Failed(_)));
+        assert!(matches!(get_item_delivery(&json!({"index": {"status": 404}}), &policy), Delivery::Failed(_)));
+        assert!(matches!(get_item_delivery(&json!({"create": {"status": 400, "error": {"type": "mapper_parsing_exception"}}}), &policy),
+            Delivery::Rejected(e) if e.contains("mapper_parsing_exception")));
+        assert!(matches!(get_item_delivery(&Value::Null, &policy), Delivery::Failed(_)));
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle JSON objects
+use serde_json::Value;
+// To format event dates
+use time::OffsetDateTime;
+// To get the file name of events
+use std::path::Path;
+
+use crate::config;
+use crate::lifecycle;
+use crate::output::Document;
+
+const VENDOR: &str = "Achiefs";
+const PRODUCT: &str = "FIM";
+// Event field, CEF extension key and LEEF attribute
+const FIELDS: [(&str, &str, &str); 19] = [
+    ("id", "externalId", "externalId"),
+    ("timestamp", "rt", "devTime"),
+    ("hostname", "dvchost", "identHostName"),
+    ("pid", "dvcpid", "pid"),
+    ("kind", "act", "cat"),
+    ("file", "filePath", "filePath"),
+    ("old_path", "oldFilePath", "oldFilePath"),
+    ("checksum", "fileHash", "fileHash"),
+    ("checksum_before", "oldFileHash", "oldFileHash"),
+    ("size", "fsize", "fileSize"),
+    ("mode", "filePermission", "filePermission"),
+    ("mode_before", "oldFilePermission", "oldFilePermission"),
+    ("mtime", "fileModificationTime", "fileModificationTime"),
+    ("labels", "cs1", "labels"),
+    ("node", "cs2", "node"),
+    ("user", "cs3", "usrName"),
+    ("group", "cs4", "groupName"),
+    ("uid", "cn1", "uid"),
+    ("gid", "cn2", "gid")
+];
+// Names of the CEF custom extensions
+const CEF_LABELS: [(&str, &str); 6] = [("cs1", "labels"), ("cs2", "node"), ("cs3", "user"), ("cs4", "group"),
+    ("cn1", "uid"), ("cn2", "gid")];
+
+// ----------------------------------------------------------------------------
+
+// Syslog severity of the event, failures of the agent are warnings and file changes notices
+pub fn get_severity(kind: &str) -> u8 {
+    match kind {
+        lifecycle::CONFIG_INVALID | lifecycle::WATCH_FAILED | lifecycle::QUEUE_OVERFLOW |
+            lifecycle::DESTINATION_UNREACHABLE => 4,
+        lifecycle::AGENT_STARTED | lifecycle::AGENT_STOPPED | lifecycle::CONFIG_RELOADED |
+            lifecycle::WATCH_ADDED | lifecycle::WATCH_REMOVED | lifecycle::HEARTBEAT => 6,
+        _ => 5
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Severity from 0 to 10 used by CEF and LEEF
+fn get_siem_severity(kind: &str) -> u8 {
+    match get_severity(kind) {
+        4 => 7,
+        5 => 5,
+        _ => 3
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Events store the milliseconds since epoch, current time is used if it can't be read
+pub fn get_time(json: &Value) -> OffsetDateTime {
+    json["timestamp"].as_str()
+        .and_then(|millis| millis.parse::<i128>().ok())
+        .and_then(|millis| OffsetDateTime::from_unix_timestamp_nanos(millis * 1_000_000).ok())
+        .unwrap_or_else(OffsetDateTime::now_utc)
+}
+
+// ----------------------------------------------------------------------------
+
+// Format a date as RFC 3339 UTC timestamp with milliseconds
+pub fn format_time(time: OffsetDateTime) -> String {
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", time.year(), time.month() as u8, time.day(),
+        time.hour(), time.minute(), time.second(), time.millisecond())
+}
+
+// ----------------------------------------------------------------------------
+
+// Get a JSON value as plain text, lists are joined with commas
+pub fn get_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        Value::Array(list) => list.iter().map(get_text).collect::<Vec<String>>().join(","),
+        other => other.to_string()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Short description of the event, agent events have their own message
+pub fn get_message(json: &Value) -> String {
+    let kind = json["kind"].as_str().unwrap_or_default();
+    match (json["message"].as_str(), json["file"].as_str(), json["path"].as_str()) {
+        (Some(message), _, _) => String::from(message),
+        (None, Some(file), _) | (None, None, Some(file)) => format!("{} {}", kind, file),
+        _ => String::from(kind)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the mapped fields of the event with a value, column selects the CEF or LEEF name
+fn get_fields(document: &dyn Document, json: &Value, column: usize) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for field in FIELDS {
+        let value = match field.0 {
+            "id" => document.get_id(),
+            // Modification time is stored in seconds, both formats expect milliseconds
+            "mtime" => json["mtime"].as_i64().map(|seconds| (seconds * 1000).to_string()).unwrap_or_default(),
+            name => get_text(&json[name])
+        };
+        if value.is_empty() { continue; }
+        let key = match column {
+            0 => field.1,
+            _ => field.2
+        };
+        fields.push((String::from(key), value));
+    }
+    fields
+}
+
+// ----------------------------------------------------------------------------
+
+// Header fields can't span several lines
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+// ----------------------------------------------------------------------------
+
+fn escape_cef_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('=', "\\=").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+// ----------------------------------------------------------------------------
+
+// Format the event as ArcSight Common Event Format line
+pub fn format_cef(document: &dyn Document) -> String {
+    let json = document.get_json();
+    let kind = json["kind"].as_str().unwrap_or_default();
+    let mut extension = Vec::new();
+    for (key, value) in get_fields(document, &json, 0) {
+        if let Some((_, label)) = CEF_LABELS.iter().find(|(custom, _)| *custom == key) {
+            extension.push(format!("{}Label={}", key, label));
+        }
+        if key == "filePath" {
+            if let Some(name) = Path::new(&value).file_name() {
+                extension.push(format!("fname={}", escape_cef_value(&name.to_string_lossy())));
+            }
+        }
+        extension.push(format!("{}={}", key, escape_cef_value(&value)));
+    }
+    extension.push(format!("msg={}", escape_cef_value(&get_message(&json))));
+    format!("CEF:0|{}|{}|{}|{}|{}|{}|{}", VENDOR, PRODUCT, config::VERSION, escape_cef_header(kind),
+        escape_cef_header(&get_message(&json)), get_siem_severity(kind), extension.join(" "))
+}
+
+// ----------------------------------------------------------------------------
+
+fn escape_leef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+// ----------------------------------------------------------------------------
+
+// Attributes are separated by tabs
+fn escape_leef_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+// ----------------------------------------------------------------------------
+
+// Format the event as IBM QRadar Log Event Extended Format line
+pub fn format_leef(document: &dyn Document) -> String {
+    let json = document.get_json();
+    let kind = json["kind"].as_str().unwrap_or_default();
+    let mut attributes = vec![format!("sev={}", get_siem_severity(kind))];
+    for (key, value) in get_fields(document, &json, 1) {
+        attributes.push(format!("{}={}", key, escape_leef_value(&value)));
+    }
+    attributes.push(format!("msg={}", escape_leef_value(&get_message(&json))));
+    format!("LEEF:1.0|{}|{}|{}|{}|{}", VENDOR, PRODUCT, config::VERSION, escape_leef_header(kind), attributes.join("\t"))
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the event as a line in the format of the output
+pub fn get_line(document: &dyn Document, format: &str) -> String {
+    match format {
+        "cef" => format_cef(document),
+        "leef" => format_leef(document),
+        _ => document.format_json()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::TestDocument;
+    use serde_json::json;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_document() -> TestDocument {
+        TestDocument::new("1234", json!({
+            "timestamp": "1700000000123",
+            "hostname": "web01",
+            "pid": 42,
+            "kind": "WRITE",
+            "file": "/etc/a=b|c\\d.conf",
+            "checksum": "abc",
+            "labels": ["etc", "app"],
+            "mode": "0644",
+            "mtime": 1700000000,
+            "user": "root",
+            "uid": 0,
+            "new_path": null
+        }))
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_cef() {
+        assert_eq!(format_cef(&create_test_document()), format!("CEF:0|Achiefs|FIM|{}|WRITE|WRITE /etc/a=b\\|c\\\\d.conf|5|\
+            externalId=1234 rt=1700000000123 dvchost=web01 dvcpid=42 act=WRITE fname=a\\=b|c\\\\d.conf \
+            filePath=/etc/a\\=b|c\\\\d.conf fileHash=abc filePermission=0644 fileModificationTime=1700000000000 \
+            cs1Label=labels cs1=etc,app cs3Label=user cs3=root cn1Label=uid cn1=0 msg=WRITE /etc/a\\=b|c\\\\d.conf", config::VERSION));
+
+        let agent = TestDocument::new("1234", json!({ "kind": "WATCH_FAILED", "message": "Unable to watch\n/tmp" }));
+        assert_eq!(format_cef(&agent), format!("CEF:0|Achiefs|FIM|{}|WATCH_FAILED|Unable to watch /tmp|7|\
+            externalId=1234 act=WATCH_FAILED msg=Unable to watch\\n/tmp", config::VERSION));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_leef() {
+        assert_eq!(format_leef(&create_test_document()), format!("LEEF:1.0|Achiefs|FIM|{}|WRITE|sev=5\t\
+            externalId=1234\tdevTime=1700000000123\tidentHostName=web01\tpid=42\tcat=WRITE\tfilePath=/etc/a=b|c\\\\d.conf\t\
+            fileHash=abc\tfilePermission=0644\tfileModificationTime=1700000000000\tlabels=etc,app\tusrName=root\tuid=0\t\
+            msg=WRITE /etc/a=b|c\\\\d.conf", config::VERSION));
+
+        let heartbeat = TestDocument::new("1234", json!({ "kind": "HEARTBEAT", "message": "Agent\talive" }));
+        assert_eq!(format_leef(&heartbeat), format!("LEEF:1.0|Achiefs|FIM|{}|HEARTBEAT|sev=3\t\
+            externalId=1234\tcat=HEARTBEAT\tmsg=Agent\\talive", config::VERSION));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_line() {
+        let document = create_test_document();
+        assert_eq!(get_line(&document, "json"), document.format_json());
+        assert!(get_line(&document, "cef").starts_with("CEF:0|"));
+        assert!(get_line(&document, "leef").starts_with("LEEF:1.0|"));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_message() {
+        assert_eq!(get_message(&json!({ "kind": "CREATE", "file": "/etc/hosts" })), "CREATE /etc/hosts");
+        assert_eq!(get_message(&json!({ "kind": "SCAN", "path": "/etc" })), "SCAN /etc");
+        assert_eq!(get_message(&json!({ "kind": "HEARTBEAT", "message": "Agent alive" })), "Agent alive");
+        assert_eq!(get_message(&json!({ "kind": "ACCEPT" })), "ACCEPT");
+    }
+}
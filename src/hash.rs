@@ -2,31 +2,105 @@
 
 // To get file checksums
 use hex::encode;
-use sha3::{Sha3_512, Digest};
-use std::io::ErrorKind;
+use digest::DynDigest;
+use sha3::{Sha3_256, Sha3_512, Digest};
+use sha2::{Sha256, Sha512};
+use sha1::Sha1;
+use md5::Md5;
+use std::io::{Read, ErrorKind};
+use std::fs::File;
+// To store checksums sorted by algorithm
+use std::collections::BTreeMap;
 // To log the program process
 use log::*;
 
+// Size of the chunks read from files
+const CHUNK_SIZE: usize = 65536;
+// Algorithm used to calculate the main event checksum
+pub const DEFAULT_ALGORITHM: &str = "sha3-512";
+pub const ALGORITHMS: [&str; 7] = ["md5", "sha1", "sha256", "sha512", "sha3-256", "sha3-512", "blake3"];
+
+enum Hasher {
+    Digest(Box<dyn DynDigest>),
+    Blake3(Box<blake3::Hasher>)
+}
+
+impl Hasher {
+    fn new(algorithm: &str) -> Option<Self> {
+        match algorithm {
+            "md5" => Some(Hasher::Digest(Box::new(Md5::new()))),
+            "sha1" => Some(Hasher::Digest(Box::new(Sha1::new()))),
+            "sha256" => Some(Hasher::Digest(Box::new(Sha256::new()))),
+            "sha512" => Some(Hasher::Digest(Box::new(Sha512::new()))),
+            "sha3-256" => Some(Hasher::Digest(Box::new(Sha3_256::new()))),
+            "sha3-512" => Some(Hasher::Digest(Box::new(Sha3_512::new()))),
+            "blake3" => Some(Hasher::Blake3(Box::new(blake3::Hasher::new()))),
+            _ => None
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Digest(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => { hasher.update(data); }
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Digest(hasher) => encode(hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
 // To calculate file content hash in sha512 format (SHA3 implementation)
-pub fn get_checksum(file: String) -> String {
-    let mut hasher = Sha3_512::new();
-    match std::fs::read_to_string(file) {
-        Ok(data) => {
-            hasher.update(&data);
-            let result = hasher.finalize().to_vec();
-            encode(result)
-        },
+// Files bigger than max_size bytes are not hashed, zero means no limit
+pub fn get_checksum(file: String, max_size: u64) -> String {
+    get_checksums(file, max_size, &[String::from(DEFAULT_ALGORITHM)]).remove(DEFAULT_ALGORITHM).unwrap()
+}
+
+// ----------------------------------------------------------------------------
+
+// To calculate file content hash with each given algorithm reading the file once
+pub fn get_checksums(file: String, max_size: u64, algorithms: &[String]) -> BTreeMap<String, String> {
+    let mut hashers: Vec<(String, Hasher)> = algorithms.iter().filter_map(|algorithm| {
+        match Hasher::new(algorithm) {
+            Some(hasher) => Some((algorithm.clone(), hasher)),
+            None => {
+                error!("Hash algorithm '{}' not supported, ignoring...", algorithm);
+                None
+            }
+        }
+    }).collect();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let result = File::open(file.clone()).and_then(|mut handle| {
+        let size = handle.metadata()?.len();
+        if max_size > 0 && size > max_size {
+            debug!("File '{}' of {} bytes exceeds max file size, ignoring...", file, size);
+            return Ok(false);
+        }
+        loop {
+            match handle.read(&mut buffer) {
+                Ok(0) => return Ok(true),
+                Ok(count) => hashers.iter_mut().for_each(|(_, hasher)| hasher.update(&buffer[..count])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    });
+
+    match result {
+        Ok(true) => hashers.into_iter().map(|(algorithm, hasher)| (algorithm, hasher.finalize())).collect(),
+        Ok(false) => hashers.into_iter().map(|(algorithm, _)| (algorithm, String::from("UNKNOWN"))).collect(),
         Err(e) => {
             match e.kind() {
-                ErrorKind::NotFound => {
-                    debug!("File Not found error ignoring...");
-                    String::from("UNKNOWN")
-                },
-                _ => {
-                    debug!("Error not handled: {:?}", e.kind());
-                    String::from("UNKNOWN")
-                },
-            }
+                ErrorKind::NotFound => debug!("File Not found error ignoring..."),
+                _ => debug!("Error not handled: {:?}", e.kind())
+            };
+            hashers.into_iter().map(|(algorithm, _)| (algorithm, String::from("UNKNOWN"))).collect()
         },
     }
 }
@@ -52,21 +126,74 @@ mod tests {
     fn test_get_checksum_file() {
         let filename = String::from("test_get_checksum_file");
         create_test_file(filename.clone());
-        assert_eq!(get_checksum(filename.clone()), String::from("46512636eeeb22dee0d60f3aba6473b1fb3258dc0c9ed6fbdbf26bed06df796bc70d4c1f6d50ca977b45f35b494e4bd9fb34e55a1576d6d9a3b5e1ab059953ee"));
+        assert_eq!(get_checksum(filename.clone(), 0), String::from("46512636eeeb22dee0d60f3aba6473b1fb3258dc0c9ed6fbdbf26bed06df796bc70d4c1f6d50ca977b45f35b494e4bd9fb34e55a1576d6d9a3b5e1ab059953ee"));
         remove_test_file(filename.clone());
     }
 
     #[test]
     fn test_get_checksum_not_exists() {
-        assert_ne!(get_checksum(String::from("not_exists")), String::from("This is a test"));
-        assert_eq!(get_checksum(String::from("not_exists")), String::from("UNKNOWN"));
+        assert_ne!(get_checksum(String::from("not_exists"), 0), String::from("This is a test"));
+        assert_eq!(get_checksum(String::from("not_exists"), 0), String::from("UNKNOWN"));
     }
 
     #[test]
     fn test_get_checksum_bad() {
         let filename = String::from("test_get_checksum_bad");
         create_test_file(filename.clone());
-        assert_ne!(get_checksum(filename.clone()), String::from("This is a test"));
+        assert_ne!(get_checksum(filename.clone(), 0), String::from("This is a test"));
+        remove_test_file(filename.clone());
+    }
+
+    #[test]
+    fn test_get_checksum_binary() {
+        let filename = String::from("test_get_checksum_binary");
+        File::create(filename.clone()).unwrap().write_all(&[0, 159, 146, 150, 255]).unwrap();
+        let checksum = get_checksum(filename.clone(), 0);
+        assert_ne!(checksum, String::from("UNKNOWN"));
+        assert_eq!(checksum.len(), 128);
+        remove_test_file(filename.clone());
+    }
+
+    #[test]
+    fn test_get_checksum_chunks() {
+        let filename = String::from("test_get_checksum_chunks");
+        let data = vec![7; CHUNK_SIZE * 2 + 10];
+        File::create(filename.clone()).unwrap().write_all(&data).unwrap();
+        assert_eq!(get_checksum(filename.clone(), 0), encode(Sha3_512::digest(&data)));
+        remove_test_file(filename.clone());
+    }
+
+    #[test]
+    fn test_get_checksums() {
+        let filename = String::from("test_get_checksums");
+        create_test_file(filename.clone());
+        let algorithms: Vec<String> = ALGORITHMS.iter().map(|algorithm| String::from(*algorithm)).collect();
+        let checksums = get_checksums(filename.clone(), 0, &algorithms);
+        assert_eq!(checksums.len(), 7);
+        assert_eq!(checksums["md5"], String::from("702edca0b2181c15d457eacac39de39b"));
+        assert_eq!(checksums["sha1"], String::from("8b6ccb43dca2040c3cfbcd7bfff0b387d4538c33"));
+        assert_eq!(checksums["sha256"], String::from("54ba1fdce5a89e0d3eee6e4c587497833bc38c3586ff02057dd6451fd2d6b640"));
+        assert_eq!(checksums["sha3-512"], get_checksum(filename.clone(), 0));
+        assert_eq!(checksums["sha512"].len(), 128);
+        assert_eq!(checksums["sha3-256"].len(), 64);
+        assert_eq!(checksums["blake3"].len(), 64);
+        remove_test_file(filename.clone());
+    }
+
+    #[test]
+    fn test_get_checksums_unknown() {
+        let algorithms = vec![String::from("md5"), String::from("bad")];
+        let checksums = get_checksums(String::from("not_exists"), 0, &algorithms);
+        assert_eq!(checksums.len(), 1);
+        assert_eq!(checksums["md5"], String::from("UNKNOWN"));
+    }
+
+    #[test]
+    fn test_get_checksum_max_size() {
+        let filename = String::from("test_get_checksum_max_size");
+        create_test_file(filename.clone());
+        assert_eq!(get_checksum(filename.clone(), 10), String::from("UNKNOWN"));
+        assert_ne!(get_checksum(filename.clone(), 15), String::from("UNKNOWN"));
         remove_test_file(filename.clone());
     }
 }
@@ -1,56 +1,70 @@
 // Copyright (C) 2021, Achiefs.
 
 // To use files IO operations.
-use tokio::fs::File;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio::fs;
 // To manage HTTP requests
-use reqwest::{Client, Body};
 use reqwest::header;
 // To log the program process
-use log::{info, debug};
+use log::{info, debug, error};
 // To manage paths
 use std::path::Path;
 // Handle time intervals
 use std::time::Duration;
 
-fn get_template_path() -> String {
-    let relative_path = "./../../config/index_template.json";
-    let config_path = "/etc/fim/index_template.json";
-    let default_path = "config/index_template.json";
-    if Path::new(default_path).exists() {
-        String::from(default_path)
-    }else if Path::new("./index_template.json").exists() {
-        String::from("./index_template.json")
-    }else if Path::new(relative_path).exists() {
-        String::from(relative_path)
+use crate::backoff;
+use crate::event;
+
+// Events of the ecs schema use their own template and indexes
+fn get_template_name(schema: &str) -> &'static str {
+    match schema {
+        "ecs" => "fim-ecs",
+        _ => "fim"
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn get_template_path(schema: &str) -> String {
+    let name = match schema {
+        "ecs" => "index_template_ecs.json",
+        _ => "index_template.json"
+    };
+    let relative_path = format!("./../../config/{}", name);
+    let config_path = format!("/etc/fim/{}", name);
+    let default_path = format!("config/{}", name);
+    if Path::new(&default_path).exists() {
+        default_path
+    }else if Path::new(&format!("./{}", name)).exists() {
+        format!("./{}", name)
+    }else if Path::new(&relative_path).exists() {
+        relative_path
     }else{
-        String::from(config_path)
+        config_path
     }
 }
 
 // ----------------------------------------------------------------------------
 
-pub async fn push_template(address: String, user: String, pass: String, insecure: bool){
-    let template_path = get_template_path();
+pub async fn push_template(endpoint: &event::Endpoint, schema: &str){
+    let template_path = get_template_path(schema);
     info!("Loaded index template from: {}", template_path);
-    let file = File::open(template_path).await.unwrap();
-    let stream = FramedRead::new(file, BytesCodec::new());
-    let body = Body::wrap_stream(stream);
-    let url = format!("{}/_template/fim", address);
+    let template = fs::read(template_path).await.unwrap();
+    let url = format!("{}/_template/{}", endpoint.address, get_template_name(schema));
 
-    let client = Client::builder()
+    let client = event::get_client(endpoint.insecure);
+    let response = backoff::send(&endpoint.get_policy(), "Push index template", || client
+        .put(&url)
         .timeout(Duration::from_secs(120))
-        .danger_accept_invalid_certs(insecure)
-        .build().unwrap();
-    let response = client
-        .put(url)
         .header(header::CONTENT_TYPE, "application/json")
-        .basic_auth(user, Some(pass))
-        .body(body)
+        .basic_auth(&endpoint.user, Some(&endpoint.pass))
+        .body(template.clone())
         .send()
-        .await;
+    ).await;
 
-    debug!("Push index template response: {:?}", response.unwrap().text().await);
+    match response {
+        Ok(response) => debug!("Push index template response: {:?}", response.text().await),
+        Err(e) => error!("Unable to push index template, error: {}", e)
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -61,14 +75,21 @@ mod tests {
 
     #[test]
     fn test_push_template() {
-        tokio_test::block_on( push_template(
-            String::from("https://127.0.0.1:9200"),
-            String::from("admin"), String::from("admin"), true) );
+        let policy = backoff::Policy { max_attempts: 1, base_delay: 0, jitter: false, statuses: Vec::new() };
+        let endpoint = event::Endpoint::new("https://127.0.0.1:9200", "admin", "admin", true, policy);
+        tokio_test::block_on( push_template(&endpoint, "fim") );
+    }
+
+    #[test]
+    fn test_get_template_name() {
+        assert_eq!(get_template_name("fim"), "fim");
+        assert_eq!(get_template_name("ecs"), "fim-ecs");
     }
 
     #[test]
     fn test_get_template_path() {
-        assert_eq!(get_template_path(), "config/index_template.json");
+        assert_eq!(get_template_path("fim"), "config/index_template.json");
+        assert_eq!(get_template_path("ecs"), "config/index_template_ecs.json");
     }
 
 }
\ No newline at end of file
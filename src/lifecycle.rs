@@ -0,0 +1,241 @@
+// Copyright (C) 2021, Achiefs.
+
+// To manage date and time
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
+// To share the agent activity counters
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+// To handle JSON objects
+use serde_json::{json, Value};
+// To manage unique event identifier
+use uuid::Uuid;
+// To get own process ID
+use std::process;
+
+use crate::config;
+use crate::output::Document;
+use crate::hash;
+
+pub const AGENT_STARTED: &str = "AGENT_STARTED";
+pub const AGENT_STOPPED: &str = "AGENT_STOPPED";
+pub const CONFIG_RELOADED: &str = "CONFIG_RELOADED";
+pub const CONFIG_INVALID: &str = "CONFIG_INVALID";
+pub const WATCH_ADDED: &str = "WATCH_ADDED";
+pub const WATCH_REMOVED: &str = "WATCH_REMOVED";
+pub const WATCH_FAILED: &str = "WATCH_FAILED";
+// Events were lost by the system, monitored paths are scanned to find them
+pub const QUEUE_OVERFLOW: &str = "QUEUE_OVERFLOW";
+pub const DESTINATION_UNREACHABLE: &str = "DESTINATION_UNREACHABLE";
+pub const HEARTBEAT: &str = "HEARTBEAT";
+
+// ----------------------------------------------------------------------------
+
+// Event about the agent itself, stored with file events
+pub struct AgentEvent {
+    pub id: String,
+    pub timestamp: String,
+    pub hostname: String,
+    pub nodename: String,
+    pub version: String,
+    pub kind: String,
+    pub message: String,
+    // Fields specific of each kind
+    pub details: Value,
+    pub pid: u32,
+    pub system: String
+}
+
+impl AgentEvent {
+    pub fn new(config: &config::Config, kind: &str, message: String, details: Value) -> Self {
+        AgentEvent {
+            id: format!("{}", Uuid::new_v4()),
+            timestamp: format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis()),
+            hostname: gethostname::gethostname().into_string().unwrap(),
+            nodename: config.nodename.clone(),
+            version: String::from(config::VERSION),
+            kind: String::from(kind),
+            message,
+            details,
+            pid: process::id(),
+            system: config.system.clone()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Document for AgentEvent {
+    fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_json(&self) -> Value {
+        let mut obj = json!({
+            "timestamp": self.timestamp.clone(),
+            "hostname": self.hostname.clone(),
+            "node": self.nodename.clone(),
+            "pid": self.pid,
+            "version": self.version.clone(),
+            "kind": self.kind.clone(),
+            "message": self.message.clone(),
+            "system": self.system.clone()
+        });
+        if let Value::Object(map) = self.details.clone() {
+            obj.as_object_mut().unwrap().extend(map);
+        }
+        obj
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Activity of the running agent reported in heartbeat events
+pub struct Stats {
+    events: AtomicU64,
+    watches: AtomicUsize,
+    // Endpoint requests sent again and the ones that failed after every attempt
+    retries: AtomicU64,
+    failures: AtomicU64,
+    last_error: Mutex<Option<String>>
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Stats {
+            events: AtomicU64::new(0),
+            watches: AtomicUsize::new(0),
+            retries: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            last_error: Mutex::new(None)
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn add_event(&self) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn add_watch(&self) {
+        self.watches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn remove_watch(&self) {
+        let _ = self.watches.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |watches| watches.checked_sub(1));
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn add_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn add_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn set_error(&self, error: String) {
+        *self.last_error.lock().unwrap() = Some(error);
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Heartbeat fields, uptime in seconds
+    pub fn get_json(&self, uptime: Duration) -> Value {
+        json!({
+            "uptime": uptime.as_secs(),
+            "watches": self.watches.load(Ordering::Relaxed),
+            "events": self.events.load(Ordering::Relaxed),
+            "delivery_retries": self.retries.load(Ordering::Relaxed),
+            "delivery_failures": self.failures.load(Ordering::Relaxed),
+            "last_error": self.last_error.lock().unwrap().clone()
+        })
+    }
+}
+
+pub static STATS: Stats = Stats::new();
+
+// ----------------------------------------------------------------------------
+
+// Checksum of the configuration file to identify the loaded version
+pub fn get_config_hash(config: &config::Config) -> String {
+    hash::get_checksums(config.path.clone(), 0, &[String::from("sha256")]).remove("sha256").unwrap()
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_agent_event() {
+        let config = config::Config::new(std::env::consts::OS);
+        let event = AgentEvent::new(&config, AGENT_STOPPED, String::from("Agent stopped"),
+            json!({ "drained": 3, "timed_out": false }));
+        let json: Value = serde_json::from_str(&event.format_json()).unwrap();
+        assert_eq!(json["kind"], "AGENT_STOPPED");
+        assert_eq!(json["id"], event.id);
+        assert_eq!(json["message"], "Agent stopped");
+        assert_eq!(json["drained"], 3);
+        assert_eq!(json["timed_out"], false);
+        assert_eq!(json["node"], config.nodename);
+
+        let filename = String::from("test_agent_event.json");
+        crate::event::log_json(filename.clone(), event.format_json()).unwrap();
+        assert_eq!(fs::read_to_string(&filename).unwrap(), format!("{}\n", event.format_json()));
+        fs::remove_file(filename).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_config_hash() {
+        let mut config = config::Config::new(std::env::consts::OS);
+        config.path = String::from("test_get_config_hash.yml");
+        fs::write(&config.path, "nodename: test").unwrap();
+        let config_hash = get_config_hash(&config);
+        assert_eq!(config_hash.len(), 64);
+        fs::write(&config.path, "nodename: other").unwrap();
+        assert_ne!(get_config_hash(&config), config_hash);
+        fs::remove_file(&config.path).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::new();
+        assert_eq!(stats.get_json(Duration::from_secs(90)), json!({
+            "uptime": 90, "watches": 0, "events": 0, "delivery_retries": 0, "delivery_failures": 0, "last_error": null
+        }));
+        stats.add_watch();
+        stats.add_watch();
+        stats.remove_watch();
+        stats.add_event();
+        stats.add_retry();
+        stats.add_retry();
+        stats.add_failure();
+        stats.set_error(String::from("Unable to watch /etc"));
+        assert_eq!(stats.get_json(Duration::from_millis(1500)), json!({
+            "uptime": 1, "watches": 1, "events": 1, "delivery_retries": 2, "delivery_failures": 1,
+            "last_error": "Unable to watch /etc"
+        }));
+        stats.remove_watch();
+        stats.remove_watch();
+        assert_eq!(stats.get_json(Duration::ZERO)["watches"], 0);
+    }
+}
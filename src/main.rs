@@ -3,16 +3,20 @@
 // To read and write directories and files, env to get Operating system
 use std::{fs, env};
 // To get file system changes
-use notify::{RecommendedWatcher, Watcher, RecursiveMode};
-use std::sync::mpsc::channel;
+use notify::{RecommendedWatcher, Watcher, RecursiveMode, RawEvent};
+use std::sync::mpsc::{channel, Receiver};
 // To log the program process
-use log::{info, error, debug};
-use simplelog::{WriteLogger, Config};
+use log::{info, error, debug, warn};
+use simplelog::{WriteLogger, TermLogger, CombinedLogger, SharedLogger, Config, TerminalMode, ColorChoice};
+// To parse command line arguments
+use clap::Parser;
 // To manage paths
-use std::path::Path;
+use std::path::{Path, PathBuf};
 // To manage date and time
-use std::time::{SystemTime, UNIX_EPOCH};
-use time::OffsetDateTime;
+use std::time::{SystemTime, UNIX_EPOCH, Duration, Instant};
+use std::sync::mpsc::RecvTimeoutError;
+// Event handling
+use notify::op::Op;
 // To manage unique event identifier
 use uuid::Uuid;
 // To use intersperse()
@@ -20,8 +24,6 @@ use itertools::Itertools;
 // To get own process ID
 use std::process;
 
-// Utils functions
-mod utils;
 // Hashing functions
 mod hash;
 // Configuration load functions
@@ -31,173 +33,763 @@ mod index;
 // Single event data management
 mod event;
 use event::Event;
+// Stored state of monitored files
+mod baseline;
+use baseline::Baseline;
+// File attributes management
+mod metadata;
+use metadata::Metadata;
+// Periodic scans of monitored paths
+mod scan;
+// Ignore and include rules of monitored paths
+mod rules;
+// Comparison of monitored files with their approved state
+mod verify;
+// Approval of changes in the approved baseline
+mod accept;
+// Configuration reload of the running agent
+mod reload;
+// Events about the agent itself
+mod lifecycle;
+// Termination signals handling
+mod shutdown;
+// Events waiting for network delivery
+mod spool;
+// Retries of failed endpoint requests
+mod backoff;
+// Destinations of produced events
+mod output;
+// Syslog receivers of events
+mod syslog;
+// Splunk HTTP Event Collector of events
+mod splunk;
+// Event formats of SIEM systems
+mod format;
+// Elastic Common Schema mapping of events
+mod ecs;
+// HTTP server and documents to test outputs
+#[cfg(test)]
+mod mock;
+// Command line arguments
+mod cli;
+use cli::{Cli, Command};
+
+// Minimum seconds between baseline writes while events are received
+const BASELINE_SAVE_INTERVAL: u64 = 5;
+// Maximum seconds to process pending events when the program is stopped
+const SHUTDOWN_TIMEOUT: u64 = 10;
 
 
 // ----------------------------------------------------------------------------
 
-fn setup_logger(config: config::Config){
+// Set the log file as output of generated logs, the terminal too in foreground mode
+fn setup_logger(config: config::Config, foreground: bool){
     // Create folders to store logs based on config.yml
     fs::create_dir_all(Path::new(&config.log_file).parent().unwrap().to_str().unwrap()).unwrap();
 
     // Create logger output to write generated logs.
-    WriteLogger::init(
-        config.get_level_filter(),
+    let level = config.get_level_filter();
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![WriteLogger::new(
+        level,
         Config::default(),
         fs::OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open(config.log_file)
             .expect("Unable to open log file")
-    ).unwrap();
+    )];
+    if foreground {
+        loggers.push(TermLogger::new(level, Config::default(), TerminalMode::Stderr, ColorChoice::Auto));
+    }
+    CombinedLogger::init(loggers).unwrap();
+}
+
+async fn process_event(outputs: &output::Outputs, event: Event){
+    lifecycle::STATS.add_event();
+    outputs.send(&event).await;
 }
 
 // ----------------------------------------------------------------------------
 
-fn setup_events(destination: &str, config: config::Config){
-    // Perform actions depending on destination
-    info!("Events destination selected: {}", destination);
-    match destination {
-        config::NETWORK_MODE => {
-            debug!("Events folder not created in network mode");
-        },
-        _ => {
-            info!("Events file: {}", config.events_file);
-            fs::create_dir_all(Path::new(&config.events_file).parent().unwrap().to_str().unwrap()).unwrap()
+// Build an event of the given file with the settings of its monitor entry
+// and the previous state of the file stored in the baseline
+fn build_event(config: &config::Config, operation: Op, path: PathBuf, index: usize, previous: Option<&baseline::Entry>) -> Event {
+    // Main checksum is calculated in the same file read than the selected ones
+    let algorithms = config.get_algorithms(index);
+    let mut checksums = hash::get_checksums(String::from(path.to_str().unwrap()), config.max_file_size,
+        &[algorithms.clone(), vec![String::from(hash::DEFAULT_ALGORITHM)]].concat());
+    let checksum = checksums[hash::DEFAULT_ALGORITHM].clone();
+    checksums.retain(|algorithm, _| algorithms.contains(algorithm));
+    let checksum_before = match previous {
+        Some(entry) => entry.checksum.clone(),
+        None => String::from("UNKNOWN")
+    };
+    // Content can't be compared if current checksum is not available
+    let content_changed = checksum_before != checksum || checksum == "UNKNOWN";
+    // Previous attributes are reported on permission or ownership changes
+    let metadata_before = match operation {
+        Op::CHMOD => previous.map(Metadata::from_entry),
+        _ => None
+    };
+
+    let current_timestamp = format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis());
+    let current_hostname = gethostname::gethostname().into_string().unwrap();
+    Event {
+        id: format!("{}", Uuid::new_v4()),
+        timestamp: current_timestamp,
+        hostname: current_hostname,
+        nodename: config.nodename.clone(),
+        version: String::from(config::VERSION),
+        operation,
+        path: path.clone(),
+        labels: config.get_labels(index),
+        kind: event::get_kind(operation),
+        checksum: checksum.clone(),
+        checksums,
+        checksum_before,
+        checksum_after: checksum,
+        content_changed,
+        metadata: Metadata::new(&path),
+        metadata_before,
+        old_path: None,
+        new_path: None,
+        moved: None,
+        pid: process::id(),
+        system: config.system.clone()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Check if the event is a write that didn't modify the file content and it shouldn't be reported
+fn is_unchanged_write(config: &config::Config, event: &Event) -> bool {
+    config.ignore_unchanged && ! event.content_changed &&
+        (event.operation == Op::WRITE || event.operation == Op::CLOSE_WRITE)
+}
+
+// ----------------------------------------------------------------------------
+
+// Report an event of the agent itself with the file events
+async fn report(outputs: &output::Outputs, config: &config::Config, kind: &str, message: String, details: serde_json::Value) {
+    if let Some(error) = details["error"].as_str() {
+        lifecycle::STATS.set_error(format!("{}: {}", message, error));
+    }
+    let event = lifecycle::AgentEvent::new(config, kind, message, details);
+    debug!("Agent event: {} {}", event.kind, event.message);
+    outputs.send(&event).await;
+}
+
+// ----------------------------------------------------------------------------
+
+// Scan the files of a monitor entry inside root reporting the ones that drifted
+// from the baseline and a summary
+async fn run_scan(outputs: &output::Outputs, config: config::Config, baseline: &mut Baseline, index: usize, root: &Path) {
+    let path = root.to_str().unwrap();
+    info!("Starting scan of: {}", path);
+    let start = Instant::now();
+    let changes = baseline.scan_path(&config, index, root);
+    let mut summary = scan::ScanSummary::new(&config, index, baseline.count(&config, index, root), start.elapsed());
+    summary.path = String::from(path);
+
+    for (operation, file, previous) in changes {
+        match operation {
+            Op::CREATE => summary.created += 1,
+            Op::WRITE => summary.written += 1,
+            Op::REMOVE => summary.removed += 1,
+            _ => summary.chmod += 1
         }
+        let event = build_event(&config, operation, PathBuf::from(file), index, previous.as_ref());
+        if is_unchanged_write(&config, &event) { continue; }
+        debug!("Drift detected: {:?}", event);
+        process_event(outputs, event).await;
     }
+    info!("Scan of {} finished in {} ms, {} files scanned", path, summary.duration, summary.scanned);
+    outputs.send(&summary).await;
 }
 
 // ----------------------------------------------------------------------------
 
-async fn push_template(destination: &str, config: config::Config){
-    // Perform actions depending on destination
-    match destination {
-        config::NETWORK_MODE|config::BOTH_MODE => {
-            // On start push template (Include check if events won't be ingested by http)
-            index::push_template(config.endpoint_address, config.endpoint_user, config.endpoint_pass, config.insecure).await;
-        },
-        _ => {
-            debug!("Template not pushed in file mode");
+// Process a change detected by the watcher, renames carry the source path of the file
+// and the event path is the destination, being the same when the rename was not paired
+async fn process_file_event(outputs: &output::Outputs, config: &config::Config, baseline: &mut Baseline,
+    operation: Op, path: PathBuf, old_path: Option<PathBuf>) {
+    // Not paired renames are files moved into monitored paths if they exist, moved out otherwise
+    let unpaired = old_path.as_ref() == Some(&path);
+    let moved_in = unpaired && path.exists();
+    let moved_out = unpaired && ! moved_in;
+    let old_path = old_path.filter(|_| ! moved_in);
+    let index = match config.get_index(&path) {
+        Some(index) => index,
+        None => {
+            debug!("Event of not monitored path ignored: {:?}", path);
+            return;
         }
+    };
+    if config.match_ignore(index, &path) {
+        debug!("Event ignored not stored in alerts");
+        return;
+    }
+
+    let previous_key = String::from(old_path.as_ref().unwrap_or(&path).to_str().unwrap());
+    let mut event = build_event(config, operation, path.clone(), index, baseline.entries.get(&previous_key));
+
+    if moved_in {
+        event.moved = Some(String::from("in"));
+        event.new_path = Some(path.clone());
+    }
+    if let Some(old) = old_path {
+        event.moved = Some(String::from(match moved_out {
+            true => "out",
+            false => match config.get_index(&old) == Some(index) {
+                true => "within",
+                false => "across"
+            }
+        }));
+        event.new_path = if moved_out { None } else { Some(path.clone()) };
+        baseline.remove(&old);
+        event.old_path = Some(old);
+    }
+
+    // Keep the baseline updated with the last known state of the file
+    if ! moved_out {
+        baseline.update(&path, event.checksum.clone());
+    }
+
+    if is_unchanged_write(config, &event) {
+        debug!("Event ignored, file content not changed: {:?}", event);
+    }else{
+        debug!("Event received: {:?}", event);
+        process_event(outputs, event).await;
     }
 }
 
 // ----------------------------------------------------------------------------
 
-async fn process_event(destination: &str, event: Event, index_name: String, config: config::Config){
-    match destination {
-        config::BOTH_MODE => {
-            event.log_event(config.events_file);
-            event.send( index_name, config.endpoint_address, config.endpoint_user, config.endpoint_pass, config.insecure).await;
-        },
-        config::NETWORK_MODE => {
-            event.send( index_name, config.endpoint_address, config.endpoint_user, config.endpoint_pass, config.insecure).await;
+// Compare monitored files with the stored baseline to report changes done while stopped
+async fn startup_scan(outputs: &output::Outputs, config: config::Config) -> Baseline {
+    let first_run = ! Path::new(&config.baseline_file).exists();
+    let mut baseline = Baseline::load(config.baseline_file.clone());
+    if first_run {
+        info!("Baseline not found, building it in: {}", config.baseline_file);
+    }else{
+        info!("Scanning monitored paths to find changes against baseline: {}", config.baseline_file);
+    }
+
+    for index in 0..config.monitor.len() {
+        let changes = baseline.scan(&config, index);
+        if first_run { continue; }
+        for (operation, file, previous) in changes {
+            let event = build_event(&config, operation, PathBuf::from(file), index, previous.as_ref());
+            if is_unchanged_write(&config, &event) { continue; }
+            debug!("Offline change detected: {:?}", event);
+            process_event(outputs, event).await;
+        }
+    }
+
+    fs::create_dir_all(Path::new(&config.baseline_file).parent().unwrap().to_str().unwrap()).unwrap();
+    baseline.save(config.baseline_file.clone());
+    init_approved(&config, &baseline);
+    baseline
+}
+
+// ----------------------------------------------------------------------------
+
+// The first stored state is the approved one, later changes have to be accepted
+fn init_approved(config: &config::Config, baseline: &Baseline) {
+    if ! Path::new(&config.approved_file).exists() {
+        info!("Approved baseline not found, storing current state in: {}", config.approved_file);
+        fs::create_dir_all(Path::new(&config.approved_file).parent().unwrap().to_str().unwrap()).unwrap();
+        baseline.clone().save(config.approved_file.clone());
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Load the configuration file selected in the command line applying its overrides
+fn load_config(cli: &Cli) -> config::Config {
+    let mut config = match &cli.config {
+        Some(path) => config::Config::from_file(path, env::consts::OS),
+        None => config::Config::new(env::consts::OS)
+    };
+    if let Some(level) = &cli.log_level {
+        config.log_level = level.clone();
+        config.log_level_override = Some(level.clone());
+    }
+    config
+}
+
+// ----------------------------------------------------------------------------
+
+// Validate the configuration file, exit code is 1 if it is not valid
+fn check_config(cli: &Cli) -> i32 {
+    let path = match &cli.config {
+        Some(path) => path.clone(),
+        None => config::get_config_path(env::consts::OS)
+    };
+    match config::Config::load(&path, env::consts::OS) {
+        Ok(config) => {
+            println!("Configuration file '{}' is valid, {} monitored paths", path, config.monitor.len());
+            0
         },
-        _ => event.log_event(config.events_file)
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
     }
 }
 
 // ----------------------------------------------------------------------------
 
+// Print checksums of a file with the given algorithms, exit code is 1 if it can't be read
+fn hash_file(file: &str, algorithms: &[String]) -> i32 {
+    if ! Path::new(file).is_file() {
+        println!("[ERROR] File '{}' not found", file);
+        return 1;
+    }
+    for (algorithm, checksum) in hash::get_checksums(String::from(file), 0, algorithms) {
+        println!("{}  {}  {}", algorithm, checksum, file);
+    }
+    0
+}
+
+// ----------------------------------------------------------------------------
+
+// Scan monitored files once reporting changes, exit code is 1 if the path is not monitored
+async fn scan_once(config: config::Config, path: Option<String>) -> i32 {
+    let outputs = output::Outputs::new(&config);
+    outputs.setup().await;
+
+    let roots = match config.get_roots(&path.into_iter().collect::<Vec<String>>()) {
+        Ok(roots) => roots,
+        Err(e) => {
+            println!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+
+    let mut baseline = Baseline::load(config.baseline_file.clone());
+    for (index, root) in roots {
+        println!("[INFO] Scanning: {}", root.display());
+        run_scan(&outputs, config.clone(), &mut baseline, index, &root).await;
+    }
+    fs::create_dir_all(Path::new(&config.baseline_file).parent().unwrap().to_str().unwrap()).unwrap();
+    baseline.save(config.baseline_file.clone());
+    init_approved(&config, &baseline);
+    outputs.flush().await;
+    0
+}
+
+// ----------------------------------------------------------------------------
+
+// Compare monitored files with their approved state, exit code is 0 if all of them match,
+// 1 if differences were found and 2 if the verification couldn't be done
+fn verify_files(config: config::Config, paths: &[String], json: bool, report_file: Option<&str>) -> i32 {
+    let roots = match config.get_roots(paths) {
+        Ok(roots) => roots,
+        Err(e) => {
+            println!("[ERROR] {}", e);
+            return 2;
+        }
+    };
+    if ! Path::new(&config.approved_file).exists() {
+        println!("[ERROR] Approved baseline '{}' not found, start FIM to build it", config.approved_file);
+        return 2;
+    }
+
+    let approved = Baseline::load(config.approved_file.clone());
+    let report = verify::verify(&config, &approved, &roots);
+    match json {
+        true => println!("{}", report.get_json()),
+        false => print!("{}", report)
+    }
+    if let Some(file) = report_file {
+        if let Err(e) = fs::write(file, serde_json::to_string_pretty(&report.get_json()).unwrap()) {
+            println!("[ERROR] Unable to write report in '{}', error: {}", file, e);
+            return 2;
+        }
+    }
+    match report.passed() {
+        true => 0,
+        false => 1
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Update the approved baseline with the selected changes and report who approved them,
+// exit code is 1 if they couldn't be accepted
+async fn accept_changes(config: config::Config, targets: &[String], since: Option<&str>, reason: &str, user: Option<&str>) -> i32 {
+    let since = match since.map(accept::parse_time).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            println!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    if ! Path::new(&config.approved_file).exists() {
+        println!("[ERROR] Approved baseline '{}' not found, start FIM to build it", config.approved_file);
+        return 1;
+    }
+
+    let mut approved = Baseline::load(config.approved_file.clone());
+    let user = String::from(user.unwrap_or(&accept::get_current_user()));
+    let mut approval = accept::Approval::new(&config, user, String::from(reason), targets.to_vec(), since);
+    approval.files = match accept::accept(&config, &mut approved, targets, since) {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            println!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    if approval.files.is_empty() {
+        println!("[INFO] No changes to accept");
+        return 0;
+    }
+
+    approved.save(config.approved_file.clone());
+    for file in &approval.files {
+        println!("ACCEPTED    {} ({})", file.path, file.status);
+    }
+    println!("[INFO] {} changes accepted by {}, run by {}", approval.files.len(), approval.user, approval.real_user);
+    let outputs = output::Outputs::new(&config);
+    outputs.setup().await;
+    outputs.send(&approval).await;
+    outputs.flush().await;
+    0
+}
+
+// ----------------------------------------------------------------------------
+
 // Main function where the magic happens
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let code = match &cli.command {
+        Some(Command::CheckConfig) => check_config(&cli),
+        Some(Command::Hash { file, algorithms }) => hash_file(file, algorithms),
+        Some(Command::Version) => {
+            println!("fim {}", config::VERSION);
+            0
+        },
+        Some(Command::Verify { paths, json, report }) => verify_files(load_config(&cli), paths, *json, report.as_deref()),
+        Some(Command::Accept { targets, all_since, reason, user }) => {
+            accept_changes(load_config(&cli), targets, all_since.as_deref(), reason, user.as_deref()).await
+        },
+        Some(Command::Scan { path }) => {
+            let config = load_config(&cli);
+            setup_logger(config.clone(), cli.foreground);
+            scan_once(config, path.clone()).await
+        },
+        Some(Command::Run) | None => {
+            run(load_config(&cli), cli.foreground).await;
+            0
+        }
+    };
+    process::exit(code);
+}
+
+// ----------------------------------------------------------------------------
+
+fn log_monitor_entry(config: &config::Config, index: usize) {
+    let entry = &config.monitor[index];
+    let path = &entry.path;
+    info!("Monitoring path: {}", path);
+    let algorithms : String = Itertools::intersperse(config.get_algorithms(index).into_iter(), String::from(", ")).collect();
+    info!("Hashing files inside {} with: {}", path, algorithms);
+    match entry.ignore.is_empty() {
+        false => {
+            let ignore_list : String = Itertools::intersperse(entry.ignore.iter().map(|r| r.to_string()), String::from(", ")).collect();
+            info!("Ignoring files with: {} inside {}", ignore_list, path);
+        },
+        true => info!("Ignore for '{}' not set", path)
+    };
+    if ! entry.include.is_empty() {
+        let include_list : String = Itertools::intersperse(entry.include.iter().map(|r| r.to_string()), String::from(", ")).collect();
+        info!("Including only files with: {} inside {}", include_list, path);
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Load the configuration file again updating watches of added and removed monitor entries,
+// the running configuration is kept if the new one is not valid
+async fn reload_config(outputs: &output::Outputs, config: &mut config::Config, watcher: &mut RecommendedWatcher, baseline: &mut Baseline) -> bool {
+    let mut new_config = match config::Config::load(&config.path, &config.system) {
+        Ok(new_config) => new_config,
+        Err(e) => {
+            error!("Configuration not reloaded, keeping the running one. {}", e);
+            report(outputs, config, lifecycle::CONFIG_INVALID, String::from("Configuration not reloaded, keeping the running one"),
+                serde_json::json!({ "config_file": config.path.clone(), "error": e.to_string() })).await;
+            return false;
+        }
+    };
+    let ignored_settings = reload::keep_restart_settings(config, &mut new_config);
+    for setting in &ignored_settings {
+        warn!("Change of '{}' requires a restart, keeping the running value", setting);
+    }
+    let changes = reload::diff_monitor(config, &new_config);
+
+    // Paths still inside another monitored path keep their watch
+    let mut unwatched = Vec::new();
+    for path in &changes.removed {
+        info!("Path removed from monitoring: {}", path);
+        if new_config.get_index(Path::new(path)).is_none() {
+            match watcher.unwatch(path) {
+                Ok(_) => {
+                    lifecycle::STATS.remove_watch();
+                    report(outputs, &new_config, lifecycle::WATCH_REMOVED, format!("Stopped watching {}", path),
+                        serde_json::json!({ "file": path })).await;
+                    unwatched.push(Path::new(path));
+                },
+                Err(e) => error!("Unable to stop watching '{}', error: {:?}", path, e)
+            }
+        }
+    }
+    for index in &changes.modified {
+        info!("Settings of monitored path updated: {}", new_config.monitor[*index].path);
+        log_monitor_entry(&new_config, *index);
+    }
+    for index in &changes.added {
+        info!("Path added to monitoring: {}", new_config.monitor[*index].path);
+        log_monitor_entry(&new_config, *index);
+    }
+    // Watches of nested paths are dropped with the removed ones so they are set again
+    for index in 0..new_config.monitor.len() {
+        let nested = unwatched.iter().any(|path| Path::new(&new_config.monitor[index].path).starts_with(path));
+        if nested && ! changes.added.contains(&index) {
+            lifecycle::STATS.remove_watch();
+        }
+        if changes.added.contains(&index) || nested {
+            watch_path(outputs, &new_config, watcher, index).await;
+        }
+    }
+
+    // Current state of added paths is stored without reporting it, entries now ignored are dropped
+    baseline.entries.retain(|key, _| {
+        let path = Path::new(key);
+        matches!(new_config.get_index(path), Some(index) if ! new_config.match_ignore(index, path))
+    });
+    for index in &changes.added {
+        baseline.scan(&new_config, *index);
+    }
+    baseline.changed = true;
+
+    match changes.is_empty() {
+        true => info!("Configuration reloaded, monitored paths not changed"),
+        false => info!("Configuration reloaded, {} paths added, {} updated and {} removed",
+            changes.added.len(), changes.modified.len(), changes.removed.len())
+    }
+    let paths = |indexes: &[usize]| indexes.iter().map(|index| new_config.monitor[*index].path.clone()).collect::<Vec<String>>();
+    report(outputs, &new_config, lifecycle::CONFIG_RELOADED, String::from("Configuration reloaded"), serde_json::json!({
+        "config_file": new_config.path.clone(),
+        "config_hash": lifecycle::get_config_hash(&new_config),
+        "paths_added": paths(&changes.added),
+        "paths_updated": paths(&changes.modified),
+        "paths_removed": changes.removed,
+        "ignored_settings": ignored_settings
+    })).await;
+    outputs.reload(&new_config);
+    *config = new_config;
+    true
+}
+
+// ----------------------------------------------------------------------------
+
+// Set the watch of a monitor entry, failures are reported and the rest of paths are still watched
+async fn watch_path(outputs: &output::Outputs, config: &config::Config, watcher: &mut RecommendedWatcher, index: usize) {
+    let path = &config.monitor[index].path;
+    match watcher.watch(path, RecursiveMode::Recursive) {
+        Ok(_) => {
+            lifecycle::STATS.add_watch();
+            report(outputs, config, lifecycle::WATCH_ADDED, format!("Watching {}", path),
+                serde_json::json!({ "file": path, "labels": config.get_labels(index) })).await;
+        },
+        Err(e) => {
+            error!("Unable to watch '{}', error: {:?}", path, e);
+            report(outputs, config, lifecycle::WATCH_FAILED, format!("Unable to watch {}", path),
+                serde_json::json!({ "file": path, "labels": config.get_labels(index), "error": format!("{:?}", e) })).await;
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Monitor configured paths until the program is stopped
+async fn run(mut config: config::Config, foreground: bool) {
     println!("Achiefs File Integrity Monitoring software started!");
-    println!("[INFO] Reading config...");
-    let config = config::Config::new(env::consts::OS);
     println!("[INFO] Log file: {}", config.log_file);
     println!("[INFO] Log level: {}", config.log_level);
 
-    setup_logger(config.clone());
-    let destination = config.get_events_destination();
-    setup_events(destination.as_str(), config.clone());
+    setup_logger(config.clone(), foreground);
+    let outputs = output::Outputs::new(&config);
+    outputs.setup().await;
 
-    // Check if we have to push index template
-    push_template(destination.as_str(), config.clone()).await;
+    // Check changes produced while FIM was stopped
+    let mut baseline = startup_scan(&outputs, config.clone()).await;
+    let mut last_save = Instant::now();
+    let mut scheduler = scan::Scheduler::new(&config);
+    let started = Instant::now();
+    let mut last_heartbeat = Instant::now();
+    let mut last_flush = Instant::now();
 
     // Iterating over monitor paths and set watcher on each folder to watch.
     let (tx, rx) = channel();
     let mut watcher: RecommendedWatcher = Watcher::new_raw(tx).unwrap();
-    for m in config.monitor.clone() {
-        let path = m["path"].as_str().unwrap();
-        info!("Monitoring path: {}", path);
-        match m["ignore"].as_vec() {
-            Some(ig) => {
-                let ignore_list_vec  = ig.iter().map(|e| { e.as_str().unwrap() });
-                let ignore_list : String = Itertools::intersperse(ignore_list_vec, ", ").collect();
-                info!("Ignoring files with: {} inside {}", ignore_list, path);
-            },
-            None => info!("Ignore for '{}' not set", path)
-        };
-        watcher.watch(path, RecursiveMode::Recursive).unwrap();
+    for index in 0..config.monitor.len() {
+        log_monitor_entry(&config, index);
+        watch_path(&outputs, &config, &mut watcher, index).await;
     }
+    let mut config_watch = reload::ConfigWatch::new(&config.path);
+    report(&outputs, &config, lifecycle::AGENT_STARTED, String::from("Agent started"), serde_json::json!({
+        "config_file": config.path.clone(),
+        "config_hash": lifecycle::get_config_hash(&config),
+        "paths": config.monitor.iter().map(|entry| entry.path.clone()).collect::<Vec<String>>(),
+        "outputs": outputs.get_names()
+    })).await;
 
     // Main loop, receive any produced event and write it into the events log.
-    loop {
-        match rx.recv() {
+    let shutdown = shutdown::Shutdown::new();
+    let mut pending_rename: Option<(u32, PathBuf)> = None;
+    while ! shutdown.requested() {
+        match rx.recv_timeout(Duration::from_secs(1)) {
             Ok(raw_event) => {
-                // Get the event path and filename
-                debug!("Event registered: {:?}", raw_event);
-                let event_path = Path::new(raw_event.path.as_ref().unwrap().to_str().unwrap());
-                let event_parent_path = event_path.parent().unwrap().to_str().unwrap();
-                let event_filename = event_path.file_name().unwrap();
-
-                // Iterate over monitoring paths to match ignore string and ignore event or not
-                let monitor_vector = config.monitor.clone().to_vec();
-                let monitor_index = monitor_vector.iter().position(|it| {
-                    let path = it["path"].as_str().unwrap();
-                    let value = if path.ends_with('/') || path.ends_with('\\'){ utils::pop(path) }else{ path };
-                    match event_parent_path.contains(value) {
-                        true => true,
-                        false => event_path.to_str().unwrap().contains(value)
-                    }
-                });
-                let index = monitor_index.unwrap();
-
-                if monitor_index.is_some() &&
-                    match monitor_vector[index]["ignore"].as_vec() {
-                        Some(igv) => ! igv.to_vec().iter().any(|ignore| event_filename.to_str().unwrap().contains(ignore.as_str().unwrap()) ),
-                        None => true
-                    }{
-
-                    let current_timestamp = format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis());
-                    let current_hostname = gethostname::gethostname().into_string().unwrap();
-                    let yaml_labels = match config.monitor[index]["labels"].clone().into_vec() {
-                        Some(lb) => lb,
-                        None => Vec::new()
-                    };
-                    let current_labels = yaml_labels.to_vec().iter().map(|element| String::from(element.as_str().unwrap()) ).collect();
-                    let operation = raw_event.op.unwrap();
-                    let path = raw_event.path.unwrap().clone();
-
-                    let event = Event {
-                        id: format!("{}", Uuid::new_v4()),
-                        timestamp: current_timestamp,
-                        hostname: current_hostname,
-                        nodename: config.nodename.clone(),
-                        version: String::from(config::VERSION),
-                        operation,
-                        path: path.clone(),
-                        labels: current_labels,
-                        kind: event::get_kind(operation),
-                        checksum: hash::get_checksum( String::from(path.to_str().unwrap()) ),
-                        pid: process::id(),
-                        system: config.system.clone()
-                    };
-
-                    let current_date = OffsetDateTime::now_utc();
-                    let index_name = format!("fim-{}-{}-{}", current_date.year(), current_date.month() as u8, current_date.day() );
-
-                    debug!("Event received: {:?}", event);
-                    process_event(destination.clone().as_str(), event, index_name.clone(), config.clone()).await;
-                }else{
-                    debug!("Event ignored not stored in alerts");
+                process_raw_event(&outputs, &config, &mut baseline, &mut pending_rename, raw_event).await;
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some((_, old_path)) = pending_rename.take() {
+                    process_file_event(&outputs, &config, &mut baseline, Op::RENAME, old_path.clone(), Some(old_path)).await;
                 }
             },
             Err(e) => error!("Watch error: {:?}", e),
         }
+
+        if config_watch.requested() && reload_config(&outputs, &mut config, &mut watcher, &mut baseline).await {
+            scheduler = scan::Scheduler::new(&config);
+        }
+
+        for (endpoint, e) in outputs.take_errors() {
+            report(&outputs, &config, lifecycle::DESTINATION_UNREACHABLE, String::from("Events endpoint unreachable"),
+                serde_json::json!({ "endpoint": endpoint, "error": e })).await;
+        }
+
+        for index in scheduler.due() {
+            run_scan(&outputs, config.clone(), &mut baseline, index, Path::new(&config.monitor[index].path)).await;
+        }
+
+        if config.heartbeat_interval > 0 && last_heartbeat.elapsed() >= Duration::from_secs(config.heartbeat_interval) {
+            let mut details = lifecycle::STATS.get_json(started.elapsed());
+            if let Some((pending, dropped)) = outputs.get_status() {
+                details["spool_pending"] = serde_json::json!(pending);
+                details["spool_dropped"] = serde_json::json!(dropped);
+            }
+            report(&outputs, &config, lifecycle::HEARTBEAT, String::from("Agent alive"), details).await;
+            last_heartbeat = Instant::now();
+        }
+
+        // Incomplete batches are sent after the batch interval
+        if last_flush.elapsed() >= Duration::from_secs(config.batch_interval) {
+            outputs.flush().await;
+            last_flush = Instant::now();
+        }
+
+        if baseline.changed && last_save.elapsed() >= Duration::from_secs(BASELINE_SAVE_INTERVAL) {
+            baseline.save(config.baseline_file.clone());
+            last_save = Instant::now();
+        }
+    }
+
+    info!("Stop requested, stopping watchers and processing pending events");
+    drop(watcher);
+    stop(&outputs, &config, &mut baseline, &mut pending_rename, rx, Duration::from_secs(SHUTDOWN_TIMEOUT)).await;
+}
+
+// ----------------------------------------------------------------------------
+
+// Process an event received from the watcher, renames are held until the event of
+// the destination path arrives
+async fn process_raw_event(outputs: &output::Outputs, config: &config::Config, baseline: &mut Baseline,
+    pending_rename: &mut Option<(u32, PathBuf)>, raw_event: RawEvent) {
+    debug!("Event registered: {:?}", raw_event);
+    // Events were lost, scan all monitored paths to find changes
+    if let Ok(Op::RESCAN) = raw_event.op {
+        info!("Rescan requested by the system, scanning all monitored paths");
+        report(outputs, config, lifecycle::QUEUE_OVERFLOW, String::from("System events lost, scanning all monitored paths"),
+            serde_json::json!({ "paths": config.monitor.iter().map(|entry| entry.path.clone()).collect::<Vec<String>>() })).await;
+        for index in 0..config.monitor.len() {
+            run_scan(outputs, config.clone(), baseline, index, Path::new(&config.monitor[index].path)).await;
+        }
+        return;
+    }else if raw_event.path.is_none() {
+        error!("Event without path received: {:?}", raw_event);
+        return;
     }
+
+    let operation = raw_event.op.unwrap();
+    let path = raw_event.path.unwrap();
+    if let (Op::RENAME, Some(event_cookie)) = (operation, raw_event.cookie) {
+        match pending_rename.take() {
+            Some((cookie, old_path)) if cookie == event_cookie => {
+                process_file_event(outputs, config, baseline, operation, path, Some(old_path)).await;
+            },
+            Some((_, old_path)) => {
+                process_file_event(outputs, config, baseline, operation, old_path.clone(), Some(old_path)).await;
+                *pending_rename = Some((event_cookie, path));
+            },
+            None => *pending_rename = Some((event_cookie, path))
+        }
+        return;
+    }
+    // Not paired renames mean the file was moved out of monitored paths
+    if let Some((_, old_path)) = pending_rename.take() {
+        process_file_event(outputs, config, baseline, Op::RENAME, old_path.clone(), Some(old_path)).await;
+    }
+    process_file_event(outputs, config, baseline, operation, path, None).await;
+}
+
+// ----------------------------------------------------------------------------
+
+// Process the events received before watchers were stopped, limited to timeout,
+// then store the baseline and report the agent was stopped
+async fn stop(outputs: &output::Outputs, config: &config::Config, baseline: &mut Baseline,
+    pending_rename: &mut Option<(u32, PathBuf)>, rx: Receiver<RawEvent>, timeout: Duration) {
+    let start = Instant::now();
+    let mut drained = 0;
+    // Elapsed time is checked between events as receiving them doesn't yield to the timeout
+    let drain = async {
+        while start.elapsed() < timeout {
+            match rx.try_recv() {
+                Ok(raw_event) => {
+                    process_raw_event(outputs, config, baseline, pending_rename, raw_event).await;
+                    drained += 1;
+                },
+                Err(_) => {
+                    if let Some((_, old_path)) = pending_rename.take() {
+                        process_file_event(outputs, config, baseline, Op::RENAME, old_path.clone(), Some(old_path)).await;
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    };
+    let timed_out = ! tokio::time::timeout(timeout, drain).await.unwrap_or(false);
+    match timed_out {
+        true => error!("Pending events not processed in {} seconds, {} events processed", timeout.as_secs(), drained),
+        false => info!("Pending events processed: {}", drained)
+    }
+    baseline.save(config.baseline_file.clone());
+
+    let stopped = report(outputs, config, lifecycle::AGENT_STOPPED, String::from("Agent stopped"),
+        serde_json::json!({ "drained": drained, "timed_out": timed_out }));
+    if tokio::time::timeout(timeout, stopped).await.is_err() {
+        error!("Stop event not sent in {} seconds", timeout.as_secs());
+    }
+    let flushed = outputs.flush();
+    if tokio::time::timeout(timeout, flushed).await.is_err() {
+        error!("Spooled events not sent in {} seconds, they will be sent on next start", timeout.as_secs());
+    }
+    info!("Achiefs File Integrity Monitoring software stopped");
+    log::logger().flush();
 }
 
 // ----------------------------------------------------------------------------
@@ -205,9 +797,8 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use notify::op::Op;
-    use std::path::PathBuf;
     use tokio_test::block_on;
+    use std::collections::BTreeMap;
 
     // ------------------------------------------------------------------------
 
@@ -215,27 +806,17 @@ mod tests {
     fn test_setup_logger() {
         let config = config::Config::new(env::consts::OS);
         fs::create_dir_all(Path::new(&config.events_file).parent().unwrap().to_str().unwrap()).unwrap();
-        setup_logger(config.clone());
-    }
-
-    // ------------------------------------------------------------------------
-
-    #[test]
-    fn test_push_template() {
-        let config = config::Config::new(env::consts::OS);
-        fs::create_dir_all(Path::new(&config.log_file).parent().unwrap().to_str().unwrap()).unwrap();
-        block_on(push_template("file", config.clone()));
-        block_on(push_template("network", config.clone()));
+        setup_logger(config.clone(), false);
     }
 
     // ------------------------------------------------------------------------
 
     #[test]
-    fn test_setup_events() {
+    fn test_setup_outputs() {
         let config = config::Config::new(env::consts::OS);
         fs::create_dir_all(Path::new(&config.log_file).parent().unwrap().to_str().unwrap()).unwrap();
-        setup_events("file", config.clone());
-        setup_events("network", config.clone());
+        block_on(output::Outputs::new(&config).setup());
+        assert!(Path::new(&config.events_file).parent().unwrap().exists());
     }
 
     // ------------------------------------------------------------------------
@@ -256,9 +837,450 @@ mod tests {
             labels: Vec::new(),
             kind: "TEST".to_string(),
             checksum: "UNKNOWN".to_string(),
+            checksums: BTreeMap::new(),
+            checksum_before: "UNKNOWN".to_string(),
+            checksum_after: "UNKNOWN".to_string(),
+            content_changed: false,
+            metadata: None,
+            metadata_before: None,
+            old_path: None,
+            new_path: None,
+            moved: None,
             pid: 0,
             system: "test".to_string()
         };
-        block_on(process_event("file", event, String::from("fim"), config.clone()));
+        block_on(process_event(&output::Outputs::new(&config), event));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_build_event() {
+        let mut config = config::Config::new(env::consts::OS);
+        let yaml = "- path: ./\n  labels: [\"test\"]\n  algorithms: [\"md5\", \"sha256\"]\n- path: ./src";
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        let event = build_event(&config, Op::WRITE, PathBuf::from("Cargo.toml"), 0, None);
+        assert_eq!(event.operation, Op::WRITE);
+        assert_eq!(event.kind, String::from("WRITE"));
+        assert_eq!(event.path, PathBuf::from("Cargo.toml"));
+        assert_eq!(event.labels, vec![String::from("test")]);
+        assert_eq!(event.checksums.keys().collect::<Vec<&String>>(), vec!["md5", "sha256"]);
+        assert_eq!(event.checksum, hash::get_checksum(String::from("Cargo.toml"), 0));
+        assert_eq!(event.nodename, config.nodename);
+        assert_eq!(event.version, String::from(config::VERSION));
+        assert_eq!(event.pid, process::id());
+        assert_ne!(event.checksum, String::from("UNKNOWN"));
+
+        let event = build_event(&config, Op::CREATE, PathBuf::from("src/main.rs"), 1, None);
+        assert_eq!(event.labels, Vec::<String>::new());
+        assert_eq!(event.checksums.keys().collect::<Vec<&String>>(), vec!["sha3-512"]);
+        assert_eq!(event.checksums["sha3-512"], event.checksum);
+        assert_eq!(event.checksum_before, String::from("UNKNOWN"));
+        assert_eq!(event.checksum_after, event.checksum);
+        assert!(event.content_changed);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_build_event_previous() {
+        let config = config::Config::new(env::consts::OS);
+        let checksum = hash::get_checksum(String::from("Cargo.toml"), 0);
+        let previous = baseline::Entry::new(Path::new("Cargo.toml"), checksum.clone()).unwrap();
+        let event = build_event(&config, Op::WRITE, PathBuf::from("Cargo.toml"), 0, Some(&previous));
+        assert_eq!(event.checksum_before, checksum);
+        assert_eq!(event.checksum_after, checksum);
+        assert!(!event.content_changed);
+
+        let previous = baseline::Entry::new(Path::new("Cargo.toml"), String::from("OLD")).unwrap();
+        let event = build_event(&config, Op::WRITE, PathBuf::from("Cargo.toml"), 0, Some(&previous));
+        assert_eq!(event.checksum_before, String::from("OLD"));
+        assert!(event.content_changed);
+
+        assert_eq!(event.metadata.unwrap().size, previous.size);
+        assert_eq!(event.metadata_before, None);
+
+        let event = build_event(&config, Op::CHMOD, PathBuf::from("Cargo.toml"), 0, Some(&previous));
+        assert_eq!(event.metadata_before, Some(Metadata::from_entry(&previous)));
+
+        let event = build_event(&config, Op::REMOVE, PathBuf::from("not_exists"), 0, Some(&previous));
+        assert_eq!(event.checksum_after, String::from("UNKNOWN"));
+        assert!(event.content_changed);
+        assert_eq!(event.metadata, None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_is_unchanged_write() {
+        let mut config = config::Config::new(env::consts::OS);
+        let checksum = hash::get_checksum(String::from("Cargo.toml"), 0);
+        let previous = baseline::Entry::new(Path::new("Cargo.toml"), checksum).unwrap();
+        let event = build_event(&config, Op::WRITE, PathBuf::from("Cargo.toml"), 0, Some(&previous));
+        assert!(!is_unchanged_write(&config, &event));
+        config.ignore_unchanged = true;
+        assert!(is_unchanged_write(&config, &event));
+        let event = build_event(&config, Op::CHMOD, PathBuf::from("Cargo.toml"), 0, Some(&previous));
+        assert!(!is_unchanged_write(&config, &event));
+        let event = build_event(&config, Op::WRITE, PathBuf::from("Cargo.toml"), 0, None);
+        assert!(!is_unchanged_write(&config, &event));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_process_file_event_rename() {
+        let root = "./test_process_file_event_rename";
+        fs::create_dir_all(format!("{}/a", root)).unwrap();
+        fs::create_dir_all(format!("{}/b", root)).unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = format!("{}/events.json", root);
+        let yaml = format!("- path: {}/a\n- path: {}/b", root, root);
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let old_path = PathBuf::from(format!("{}/a/file", root));
+        let new_path = PathBuf::from(format!("{}/b/file", root));
+        fs::write(&old_path, "This is a test!").unwrap();
+        let mut baseline = Baseline::new();
+        baseline.update(&old_path, hash::get_checksum(String::from(old_path.to_str().unwrap()), 0));
+
+        fs::rename(&old_path, &new_path).unwrap();
+        block_on(process_file_event(&output::Outputs::new(&config), &config, &mut baseline, Op::RENAME, new_path.clone(), Some(old_path.clone())));
+        assert!(!baseline.entries.contains_key(old_path.to_str().unwrap()));
+        assert!(baseline.entries.contains_key(new_path.to_str().unwrap()));
+
+        fs::remove_file(&new_path).unwrap();
+        block_on(process_file_event(&output::Outputs::new(&config), &config, &mut baseline, Op::RENAME, new_path.clone(), Some(new_path.clone())));
+        assert!(baseline.entries.is_empty());
+
+        // File moved from a not monitored path is hashed into the baseline
+        fs::write(&new_path, "Moved in!").unwrap();
+        block_on(process_file_event(&output::Outputs::new(&config), &config, &mut baseline, Op::RENAME, new_path.clone(), Some(new_path.clone())));
+        let checksum = hash::get_checksum(String::from(new_path.to_str().unwrap()), 0);
+        assert_eq!(baseline.entries[new_path.to_str().unwrap()].checksum, checksum);
+        assert_eq!(baseline.entries.len(), 1);
+
+        let contents = fs::read_to_string(&config.events_file).unwrap();
+        let events: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["kind"], "RENAME");
+        assert_eq!(events[0]["old_path"], old_path.to_str().unwrap());
+        assert_eq!(events[0]["new_path"], new_path.to_str().unwrap());
+        assert_eq!(events[0]["moved"], "across");
+        assert_eq!(events[0]["content_changed"], false);
+        assert_eq!(events[1]["old_path"], new_path.to_str().unwrap());
+        assert!(events[1]["new_path"].is_null());
+        assert_eq!(events[1]["moved"], "out");
+        assert!(events[2]["old_path"].is_null());
+        assert_eq!(events[2]["new_path"], new_path.to_str().unwrap());
+        assert_eq!(events[2]["moved"], "in");
+        assert_eq!(events[2]["checksum"], checksum);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_process_raw_event_moved() {
+        let root = env::current_dir().unwrap().join("test_process_raw_event_moved");
+        fs::create_dir_all(root.join("monitored")).unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        let yaml = format!("- path: {}", root.join("monitored").to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let outputs = output::Outputs::new(&config);
+        let inside = root.join("monitored/file");
+        let mut baseline = Baseline::new();
+        let mut pending_rename = None;
+
+        // Moved in from outside, only the destination event arrives
+        fs::write(&inside, "This is a test!").unwrap();
+        block_on(process_raw_event(&outputs, &config, &mut baseline, &mut pending_rename,
+            RawEvent { path: Some(inside.clone()), op: Ok(Op::RENAME), cookie: Some(1) }));
+        assert!(pending_rename.is_some());
+        block_on(process_raw_event(&outputs, &config, &mut baseline, &mut pending_rename,
+            RawEvent { path: Some(root.join("monitored")), op: Ok(Op::CHMOD), cookie: None }));
+        assert!(baseline.entries[inside.to_str().unwrap()].checksum != "UNKNOWN");
+
+        // Moved out to outside, only the source event arrives
+        fs::rename(&inside, root.join("file")).unwrap();
+        block_on(process_raw_event(&outputs, &config, &mut baseline, &mut pending_rename,
+            RawEvent { path: Some(inside.clone()), op: Ok(Op::RENAME), cookie: Some(2) }));
+        block_on(process_raw_event(&outputs, &config, &mut baseline, &mut pending_rename,
+            RawEvent { path: Some(root.join("monitored")), op: Ok(Op::CHMOD), cookie: None }));
+        assert!(!baseline.entries.contains_key(inside.to_str().unwrap()));
+
+        let contents = fs::read_to_string(&config.events_file).unwrap();
+        let moved: Vec<String> = contents.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|event| event["kind"] == "RENAME")
+            .map(|event| String::from(event["moved"].as_str().unwrap())).collect();
+        assert_eq!(moved, vec!["in", "out"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_load_config() {
+        let cli = Cli::parse_from(["fim", "--config", "config/linux/config.yml", "--log-level", "debug"]);
+        let config = load_config(&cli);
+        assert_eq!(config.path, String::from("config/linux/config.yml"));
+        assert_eq!(config.log_level, String::from("debug"));
+        assert_eq!(config.log_level_override, Some(String::from("debug")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_check_config() {
+        assert_eq!(check_config(&Cli::parse_from(["fim", "-c", "config/linux/config.yml", "check-config"])), 0);
+        assert_eq!(check_config(&Cli::parse_from(["fim", "-c", "README.md", "check-config"])), 1);
+        assert_eq!(check_config(&Cli::parse_from(["fim", "-c", "not_found", "check-config"])), 1);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_hash_file() {
+        assert_eq!(hash_file("LICENSE", &[String::from("sha256"), String::from("md5")]), 0);
+        assert_eq!(hash_file("not_found", &[String::from("sha256")]), 1);
+        assert_eq!(hash_file("src", &[String::from("sha256")]), 1);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scan_once() {
+        let root = env::current_dir().unwrap().join("test_scan_once");
+        fs::create_dir_all(root.join("subdir")).unwrap();
+        fs::write(root.join("file1"), "This is a test!").unwrap();
+        fs::write(root.join("subdir/file2"), "Another test!").unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        config.baseline_file = String::from(root.join("baseline.json").to_str().unwrap());
+        config.approved_file = String::from(root.join("baseline.approved.json").to_str().unwrap());
+        let yaml = format!("- path: {}\n  ignore: [\".json\"]", root.to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(block_on(scan_once(config.clone(), Some(String::from("test_scan_once/subdir")))), 0);
+        // First stored state is the approved one
+        assert_eq!(Baseline::load(config.approved_file.clone()).entries.len(), 1);
+        let baseline = Baseline::load(config.baseline_file.clone());
+        assert_eq!(baseline.entries.len(), 1);
+        assert!(baseline.entries.contains_key(root.join("subdir/file2").to_str().unwrap()));
+
+        assert_eq!(block_on(scan_once(config.clone(), None)), 0);
+        assert_eq!(Baseline::load(config.baseline_file.clone()).entries.len(), 2);
+        let events = fs::read_to_string(&config.events_file).unwrap();
+        assert_eq!(events.lines().filter(|line| line.contains("\"SCAN\"")).count(), 2);
+
+        assert_eq!(block_on(scan_once(config.clone(), Some(String::from("/not_monitored")))), 1);
+        assert_eq!(Baseline::load(config.approved_file.clone()).entries.len(), 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_verify_files() {
+        let root = env::current_dir().unwrap().join("test_verify_files");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("file1"), "This is a test!").unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        config.baseline_file = String::from(root.join("baseline.json").to_str().unwrap());
+        config.approved_file = String::from(root.join("baseline.approved.json").to_str().unwrap());
+        let yaml = format!("- path: {}\n  ignore: [\".json\"]", root.to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let report = root.join("report.json");
+        let report_file = Some(report.to_str().unwrap());
+
+        // Approved baseline not built yet
+        assert_eq!(verify_files(config.clone(), &[], false, None), 2);
+        assert_eq!(block_on(scan_once(config.clone(), None)), 0);
+        assert_eq!(verify_files(config.clone(), &[], false, report_file), 0);
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["result"], "pass");
+
+        fs::write(root.join("file1"), "Changed content").unwrap();
+        assert_eq!(verify_files(config.clone(), &[String::from("test_verify_files")], true, report_file), 1);
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["result"], "fail");
+        assert_eq!(json["differences"][0]["status"], "modified");
+
+        assert_eq!(verify_files(config.clone(), &[String::from("/not_monitored")], false, None), 2);
+        assert_eq!(verify_files(config, &[], false, Some("not_found/report.json")), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_accept_changes() {
+        let root = env::current_dir().unwrap().join("test_accept_changes");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("file1"), "This is a test!").unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        config.baseline_file = String::from(root.join("baseline.json").to_str().unwrap());
+        config.approved_file = String::from(root.join("baseline.approved.json").to_str().unwrap());
+        let yaml = format!("- path: {}\n  ignore: [\".json\"]", root.to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let targets = [String::from("test_accept_changes/file1")];
+
+        // Approved baseline not built yet
+        assert_eq!(block_on(accept_changes(config.clone(), &targets, None, "Test", None)), 1);
+        assert_eq!(block_on(scan_once(config.clone(), None)), 0);
+        fs::write(root.join("file1"), "Changed content").unwrap();
+        assert_eq!(verify_files(config.clone(), &[], false, None), 1);
+
+        assert_eq!(block_on(accept_changes(config.clone(), &[], Some("bad"), "Test", None)), 1);
+        assert_eq!(block_on(accept_changes(config.clone(), &targets, None, "Config push", Some("admin"))), 0);
+        assert_eq!(verify_files(config.clone(), &[], false, None), 0);
+        let events = fs::read_to_string(&config.events_file).unwrap();
+        let approval: serde_json::Value = serde_json::from_str(events.lines().last().unwrap()).unwrap();
+        assert_eq!(approval["kind"], "ACCEPT");
+        assert_eq!(approval["user"], "admin");
+        assert_eq!(approval["reason"], "Config push");
+        assert_eq!(approval["files"][0]["file"], root.join("file1").to_str().unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_reload_config() {
+        let root = env::current_dir().unwrap().join("test_reload_config");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a/file1"), "This is a test!").unwrap();
+        fs::write(root.join("b/file2"), "Another test!").unwrap();
+        let config_file = root.join("config.yml");
+        let config_path = config_file.to_str().unwrap();
+        let write_config = |monitor: &str| {
+            fs::write(&config_file, format!("nodename: test\nevents:\n  destination: file\n  file: {0}/events.json\n\
+                baseline:\n  file: {0}/baseline.json\nlog:\n  file: {0}/fim.log\n  level: info\nmonitor:\n{1}",
+                root.to_str().unwrap(), monitor)).unwrap();
+        };
+        let path_a = root.join("a");
+        let path_b = root.join("b");
+        let (path_a, path_b) = (path_a.to_str().unwrap(), path_b.to_str().unwrap());
+
+        write_config(&format!("  - path: {}\n", path_a));
+        let mut config = config::Config::load(config_path, env::consts::OS).unwrap();
+        let outputs = output::Outputs::new(&config);
+        let mut baseline = Baseline::new();
+        baseline.scan(&config, 0);
+        let (tx, _rx) = channel();
+        let mut watcher: RecommendedWatcher = Watcher::new_raw(tx).unwrap();
+        watcher.watch(path_a, RecursiveMode::Recursive).unwrap();
+        lifecycle::STATS.add_watch();
+
+        // Invalid configuration keeps the running one
+        fs::write(&config_file, "monitor: [").unwrap();
+        assert!(!block_on(reload_config(&outputs, &mut config, &mut watcher, &mut baseline)));
+        assert_eq!(config.monitor.len(), 1);
+
+        write_config(&format!("  - path: {}\n  - path: {}\n    labels: [\"b\"]\n", path_a, path_b));
+        assert!(block_on(reload_config(&outputs, &mut config, &mut watcher, &mut baseline)));
+        assert_eq!(config.monitor.len(), 2);
+        assert_eq!(config.get_labels(1), vec![String::from("b")]);
+        assert_eq!(baseline.entries.len(), 2);
+
+        write_config(&format!("  - path: {}\n    ignore: [\"file\"]\n", path_b));
+        assert!(block_on(reload_config(&outputs, &mut config, &mut watcher, &mut baseline)));
+        assert_eq!(config.monitor.len(), 1);
+        assert!(config.match_ignore(0, &root.join("b/file2")));
+        assert!(baseline.entries.is_empty());
+        // Files ignored after the reload are not reported as removed
+        assert!(baseline.scan(&config, 0).is_empty());
+        // Unwatched path can be watched again
+        watcher.watch(path_a, RecursiveMode::Recursive).unwrap();
+
+        let events = fs::read_to_string(&config.events_file).unwrap();
+        let events: Vec<serde_json::Value> = events.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let kinds: Vec<&str> = events.iter().map(|event| event["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, vec!["CONFIG_INVALID", "WATCH_ADDED", "CONFIG_RELOADED", "WATCH_REMOVED", "CONFIG_RELOADED"]);
+        assert_eq!(events[1]["file"], path_b);
+        assert_eq!(events[2]["paths_added"], serde_json::json!([path_b]));
+        assert_eq!(events[4]["paths_updated"], serde_json::json!([path_b]));
+        assert_eq!(events[4]["paths_removed"], serde_json::json!([path_a]));
+        assert_eq!(events[4]["config_hash"].as_str().unwrap().len(), 64);
+
+        // Nested paths watched again are counted once
+        let watches = || lifecycle::STATS.get_json(Duration::ZERO)["watches"].as_u64().unwrap();
+        let watches_before = watches();
+        write_config(&format!("  - path: {}\n  - path: {}\n", root.to_str().unwrap(), path_b));
+        assert!(block_on(reload_config(&outputs, &mut config, &mut watcher, &mut baseline)));
+        assert_eq!(watches(), watches_before + 1);
+        write_config(&format!("  - path: {}\n", path_b));
+        assert!(block_on(reload_config(&outputs, &mut config, &mut watcher, &mut baseline)));
+        assert_eq!(watches(), watches_before);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_stop() {
+        let root = env::current_dir().unwrap().join("test_stop");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("file1"), "This is a test!").unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        config.baseline_file = String::from(root.join("baseline.json").to_str().unwrap());
+        let yaml = format!("- path: {}\n  ignore: [\".json\"]", root.to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+
+        let (tx, rx) = channel();
+        tx.send(RawEvent { path: Some(root.join("file1")), op: Ok(Op::CREATE), cookie: None }).unwrap();
+        tx.send(RawEvent { path: Some(root.join("file2")), op: Ok(Op::RENAME), cookie: Some(1) }).unwrap();
+        let mut baseline = Baseline::new();
+        let mut pending_rename = None;
+        block_on(stop(&output::Outputs::new(&config), &config, &mut baseline, &mut pending_rename, rx, Duration::from_secs(SHUTDOWN_TIMEOUT)));
+
+        // Pending rename is reported as moved out
+        let events = fs::read_to_string(&config.events_file).unwrap();
+        let events: Vec<serde_json::Value> = events.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["kind"], "CREATE");
+        assert_eq!(events[1]["kind"], "RENAME");
+        assert_eq!(events[1]["moved"], "out");
+        assert_eq!(events[2]["kind"], "AGENT_STOPPED");
+        assert_eq!(events[2]["drained"], 2);
+        assert_eq!(events[2]["timed_out"], false);
+        assert!(pending_rename.is_none());
+        let baseline = Baseline::load(config.baseline_file.clone());
+        assert_eq!(baseline.entries.keys().collect::<Vec<&String>>(), vec![root.join("file1").to_str().unwrap()]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_stop_timeout() {
+        let root = env::current_dir().unwrap().join("test_stop_timeout");
+        fs::create_dir_all(&root).unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        config.events_file = String::from(root.join("events.json").to_str().unwrap());
+        config.baseline_file = String::from(root.join("baseline.json").to_str().unwrap());
+        let yaml = format!("- path: {}\n  ignore: [\".json\"]", root.to_str().unwrap());
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+
+        // Events left in the queue are not processed once the time is over
+        let (tx, rx) = channel();
+        for index in 0..3 {
+            tx.send(RawEvent { path: Some(root.join(format!("file{}", index))), op: Ok(Op::CREATE), cookie: None }).unwrap();
+        }
+        let mut baseline = Baseline::new();
+        let mut pending_rename = None;
+        block_on(stop(&output::Outputs::new(&config), &config, &mut baseline, &mut pending_rename, rx, Duration::ZERO));
+
+        let events = fs::read_to_string(&config.events_file).unwrap();
+        let events: Vec<serde_json::Value> = events.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["kind"], "AGENT_STOPPED");
+        assert_eq!(events[0]["drained"], 0);
+        assert_eq!(events[0]["timed_out"], true);
+        fs::remove_dir_all(root).unwrap();
     }
 }
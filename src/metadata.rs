@@ -0,0 +1,242 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle files and folders
+use std::fs;
+// To manage paths
+use std::path::Path;
+// To manage date and time
+#[cfg(not(unix))]
+use std::time::{SystemTime, UNIX_EPOCH};
+// To handle JSON objects
+use serde_json::{json, Value};
+// To log the program process
+use log::*;
+
+use crate::baseline::Entry;
+
+// File attributes reported in events, times are set in seconds since epoch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub inode: u64,
+    pub device: u64,
+    pub nlink: u64,
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64
+}
+
+impl Metadata {
+    // Read file attributes without following links, None if the file can't be reached
+    pub fn new(path: &Path) -> Option<Self> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => Some(from_fs_metadata(&metadata)),
+            Err(e) => {
+                debug!("Cannot read metadata of {:?}, error: {:?}", path, e.kind());
+                None
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Build from the attributes stored in the baseline
+    pub fn from_entry(entry: &Entry) -> Self {
+        Metadata {
+            size: entry.size,
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            inode: 0,
+            device: 0,
+            nlink: 0,
+            atime: 0,
+            mtime: entry.mtime,
+            ctime: 0
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn get_json(&self) -> Value {
+        json!({
+            "size": self.size,
+            "mode": format_mode(self.mode),
+            "uid": self.uid,
+            "gid": self.gid,
+            "user": get_user_name(self.uid),
+            "group": get_group_name(self.gid),
+            "inode": self.inode,
+            "device": self.device,
+            "nlink": self.nlink,
+            "atime": self.atime,
+            "mtime": self.mtime,
+            "ctime": self.ctime
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Get permission bits in octal format like "0644"
+pub fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode & 0o7777)
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(not(unix))]
+fn get_seconds(time: std::io::Result<SystemTime>) -> i64 {
+    match time {
+        Ok(value) => match value.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64)
+        },
+        Err(_) => 0
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(unix)]
+fn from_fs_metadata(metadata: &fs::Metadata) -> Metadata {
+    use std::os::unix::fs::MetadataExt;
+    Metadata {
+        size: metadata.len(),
+        mode: metadata.mode(),
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        inode: metadata.ino(),
+        device: metadata.dev(),
+        nlink: metadata.nlink(),
+        atime: metadata.atime(),
+        mtime: metadata.mtime(),
+        ctime: metadata.ctime()
+    }
+}
+
+#[cfg(not(unix))]
+fn from_fs_metadata(metadata: &fs::Metadata) -> Metadata {
+    Metadata {
+        size: metadata.len(),
+        mode: match metadata.permissions().readonly() {
+            true => 0o444,
+            false => 0o666
+        },
+        uid: 0,
+        gid: 0,
+        inode: 0,
+        device: 0,
+        nlink: 1,
+        atime: get_seconds(metadata.accessed()),
+        mtime: get_seconds(metadata.modified()),
+        // Creation time is used as there is no change time
+        ctime: get_seconds(metadata.created())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(unix)]
+pub fn get_user_name(uid: u32) -> String {
+    match uzers::get_user_by_uid(uid) {
+        Some(user) => String::from(user.name().to_string_lossy()),
+        None => String::from("UNKNOWN")
+    }
+}
+
+#[cfg(not(unix))]
+pub fn get_user_name(_uid: u32) -> String {
+    String::from("UNKNOWN")
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(unix)]
+pub fn get_group_name(gid: u32) -> String {
+    match uzers::get_group_by_gid(gid) {
+        Some(group) => String::from(group.name().to_string_lossy()),
+        None => String::from("UNKNOWN")
+    }
+}
+
+#[cfg(not(unix))]
+pub fn get_group_name(_gid: u32) -> String {
+    String::from("UNKNOWN")
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_new() {
+        let filename = "test_metadata_new";
+        fs::write(filename, "This is a test!").unwrap();
+        let metadata = Metadata::new(Path::new(filename)).unwrap();
+        assert_eq!(metadata.size, 15);
+        assert!(metadata.mtime > 0);
+        assert!(metadata.nlink >= 1);
+        fs::remove_file(filename).unwrap();
+
+        assert_eq!(Metadata::new(Path::new("not_exists")), None);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_from_entry() {
+        let entry = Entry {
+            path: String::from("test"), size: 10, mode: 0o100644, uid: 0, gid: 0,
+            mtime: 1000, checksum: String::from("A")
+        };
+        let metadata = Metadata::from_entry(&entry);
+        assert_eq!(metadata.size, 10);
+        assert_eq!(metadata.mode, 0o100644);
+        assert_eq!(metadata.mtime, 1000);
+        assert_eq!(metadata.inode, 0);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_json() {
+        let metadata = Metadata {
+            size: 10, mode: 0o100755, uid: 0, gid: 0, inode: 1, device: 2,
+            nlink: 1, atime: 3, mtime: 4, ctime: 5
+        };
+        let json = metadata.get_json();
+        assert_eq!(json["size"], 10);
+        assert_eq!(json["mode"], "0755");
+        assert_eq!(json["inode"], 1);
+        assert_eq!(json["device"], 2);
+        assert_eq!(json["ctime"], 5);
+        assert_eq!(json["user"], get_user_name(0));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_mode() {
+        assert_eq!(format_mode(0o100644), "0644");
+        assert_eq!(format_mode(0o104755), "4755");
+        assert_eq!(format_mode(0o600), "0600");
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_names() {
+        assert_eq!(get_user_name(0), String::from("root"));
+        assert_eq!(get_group_name(0), String::from("root"));
+        assert_eq!(get_user_name(4294967294), String::from("UNKNOWN"));
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright (C) 2021, Achiefs.
+
+// To serve HTTP requests in tests
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+// To build documents written to outputs
+use serde_json::Value;
+
+use crate::output::Document;
+
+// ----------------------------------------------------------------------------
+
+// Document with fixed identifier and data to test outputs
+pub struct TestDocument {
+    pub id: String,
+    pub data: Value
+}
+
+impl TestDocument {
+    pub fn new(id: &str, data: Value) -> Self {
+        TestDocument { id: String::from(id), data }
+    }
+}
+
+impl Document for TestDocument {
+    fn get_id(&self) -> String { self.id.clone() }
+    fn get_json(&self) -> Value { self.data.clone() }
+}
+
+// ----------------------------------------------------------------------------
+
+// Answer each request with the next status, the received requests are returned when done
+pub fn start_server(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+    start_server_with(statuses.into_iter().map(|status| (status, String::new())).collect())
+}
+
+// ----------------------------------------------------------------------------
+
+// Answer each request with the next status and body
+pub fn start_server_with(responses: Vec<(u16, String)>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Read headers and the body announced by them
+            while let Ok(read) = stream.read(&mut buffer) {
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text.lines().find_map(|line| line.to_lowercase().strip_prefix("content-length: ")
+                        .map(|value| value.parse::<usize>().unwrap())).unwrap_or(0);
+                    if request.len() >= end + 4 + length || read == 0 { break; }
+                }
+            }
+            requests.push(String::from_utf8_lossy(&request).to_string());
+            write!(stream, "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, body.len(), body).unwrap();
+        }
+        requests
+    });
+    (address, handle)
+}
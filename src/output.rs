@@ -0,0 +1,470 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle JSON objects
+use serde_json::{json, to_string, Value};
+// To write sinks returning futures from trait objects
+use std::future::Future;
+use std::pin::Pin;
+// To create the folder of events files
+use std::fs;
+use std::path::Path;
+// To hold the spool of each output
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+// To name indexes by date
+use time::OffsetDateTime;
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::event::{self, Delivery};
+use crate::index;
+use crate::spool;
+use crate::backoff;
+use crate::syslog;
+use crate::splunk;
+use crate::format;
+use crate::ecs;
+
+// Seconds between delivery attempts of spooled events while the endpoint is unreachable
+const SPOOL_RETRY_INTERVAL: u64 = 30;
+
+// ----------------------------------------------------------------------------
+
+// Event produced by FIM that can be written to any output
+pub trait Document {
+    fn get_id(&self) -> String;
+
+    // ------------------------------------------------------------------------
+
+    fn get_json(&self) -> Value;
+
+    // ------------------------------------------------------------------------
+
+    // Get formatted string with all required data
+    fn format_json(&self) -> String {
+        let mut obj = self.get_json();
+        obj["id"] = json!(self.get_id());
+        to_string(&obj).unwrap()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + 'a>>;
+
+// Destination of events, new types of outputs implement it and are built in get_sink
+pub trait EventSink {
+    // Prepare the destination before the first event is written
+    fn setup(&self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn write<'a>(&'a self, document: &'a dyn Document) -> SinkFuture<'a>;
+
+    // ------------------------------------------------------------------------
+
+    // Deliver the events held by the sink
+    fn flush(&self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Apply the settings of a reloaded configuration that don't require a restart
+    fn reload(&self, _config: &config::Config) {}
+
+    // ------------------------------------------------------------------------
+
+    // Get the error that made the destination unreachable if it wasn't already taken
+    fn take_error(&self) -> Option<String> {
+        None
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the number of events waiting for delivery and the dropped ones
+    fn get_status(&self) -> Option<(usize, u64)> {
+        None
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Events file with one event per line
+pub struct FileSink {
+    file: String,
+    format: String
+}
+
+impl EventSink for FileSink {
+    fn setup(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            info!("Events file: {}", self.file);
+            let parent = Path::new(&self.file).parent().unwrap();
+            fs::create_dir_all(parent).map_err(|e| format!("Unable to create folder '{}', error: {}", parent.display(), e))
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn write<'a>(&'a self, document: &'a dyn Document) -> SinkFuture<'a> {
+        Box::pin(async {
+            event::log_json(self.file.clone(), format::get_line(document, &self.format)).map_err(|e| e.to_string())
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Elasticsearch or OpenSearch endpoint, events are indexed by date. Each output has its own
+// spool folder, retry policy, batch limits and endpoint state
+pub struct ElasticsearchSink {
+    settings: config::Output,
+    endpoint: event::Endpoint,
+    // Spool and batch settings
+    config: config::Config,
+    spool: Mutex<Option<spool::Spool>>,
+    last_flush: Mutex<Instant>
+}
+
+impl ElasticsearchSink {
+    pub fn new(settings: &config::Output, config: &config::Config) -> Self {
+        ElasticsearchSink {
+            settings: settings.clone(),
+            endpoint: event::Endpoint::new(&settings.address, &settings.user, &settings.pass, settings.insecure,
+                backoff::Policy::new(config, settings)),
+            config: config.clone(),
+            spool: Mutex::new(None),
+            last_flush: Mutex::new(Instant::now())
+        }
+    }
+}
+
+impl EventSink for ElasticsearchSink {
+    // Events left in the spool by previous runs are sent first
+    fn setup(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            *self.spool.lock().unwrap() = spool::open(&self.config, &self.settings);
+            let delivered = spool::flush(&self.spool, &self.endpoint).await;
+            if delivered > 0 {
+                info!("Spooled events delivered: {}", delivered);
+            }
+            index::push_template(&self.endpoint, &self.settings.schema).await;
+            Ok(())
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Events are stored in the spool first so they are not lost while the endpoint is
+    // unreachable, they are sent in bulk once a batch is complete
+    fn write<'a>(&'a self, document: &'a dyn Document) -> SinkFuture<'a> {
+        Box::pin(async {
+            let url = format!("{}/{}/_doc/{}", self.endpoint.address, get_index_name(&self.settings.schema), document.get_id());
+            let data = document.get_json();
+            let spooled = self.spool.lock().unwrap().as_mut().map(|spool| (spool.push(&url, &data), spool.is_batch_ready()));
+            match spooled {
+                Some((true, ready)) => {
+                    if ready && self.endpoint.reachability.is_reachable() {
+                        spool::flush(&self.spool, &self.endpoint).await;
+                    }
+                    Ok(())
+                },
+                Some((false, _)) => Err(String::from("event dropped, the spool is full or not writable")),
+                None => match self.endpoint.post_json(&data, &url).await {
+                    Delivery::Delivered => Ok(()),
+                    Delivery::Rejected(e) | Delivery::Failed(e) => Err(e)
+                }
+            }
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Spooled events are sent less often while the endpoint is unreachable
+    fn flush(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            if ! self.endpoint.reachability.is_reachable() &&
+                self.last_flush.lock().unwrap().elapsed() < Duration::from_secs(SPOOL_RETRY_INTERVAL) {
+                return Ok(());
+            }
+            *self.last_flush.lock().unwrap() = Instant::now();
+            let delivered = spool::flush(&self.spool, &self.endpoint).await;
+            debug!("Spooled events delivered: {}", delivered);
+            Ok(())
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn reload(&self, config: &config::Config) {
+        self.endpoint.set_policy(backoff::Policy::new(config, &self.settings));
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn take_error(&self) -> Option<String> {
+        self.endpoint.reachability.take_error()
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_status(&self) -> Option<(usize, u64)> {
+        self.spool.lock().unwrap().as_ref().map(|spool| spool.get_status())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Events of the ecs schema are stored apart as their fields don't match the FIM ones
+pub fn get_index_name(schema: &str) -> String {
+    let current_date = OffsetDateTime::now_utc();
+    let prefix = match schema {
+        "ecs" => "fim-ecs",
+        _ => "fim"
+    };
+    format!("{}-{}-{}-{}", prefix, current_date.year(), current_date.month() as u8, current_date.day() )
+}
+
+// ----------------------------------------------------------------------------
+
+fn get_sink(output: &config::Output, config: &config::Config) -> Box<dyn EventSink> {
+    match output.kind.as_str() {
+        "elasticsearch" => Box::new(ElasticsearchSink::new(output, config)),
+        "syslog" => Box::new(syslog::SyslogSink::new(output)),
+        "splunk" => Box::new(splunk::SplunkSink::new(output, config)),
+        _ => Box::new(FileSink { file: output.file.clone(), format: output.format.clone() })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+struct Output {
+    settings: config::Output,
+    sink: Box<dyn EventSink>
+}
+
+impl Output {
+    fn matches(&self, json: &Value) -> bool {
+        let kind = json["kind"].as_str().unwrap_or_default();
+        let labels = json["labels"].as_array().cloned().unwrap_or_default();
+        (self.settings.kinds.is_empty() || self.settings.kinds.iter().any(|expected| expected == kind)) &&
+            (self.settings.labels.is_empty() || self.settings.labels.iter().any(|label| labels.contains(&json!(label))))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Configured outputs, each event is written to all of them and a failing one doesn't stop the rest
+pub struct Outputs {
+    outputs: Vec<Output>
+}
+
+impl Outputs {
+    pub fn new(config: &config::Config) -> Self {
+        Outputs {
+            outputs: config.get_outputs().into_iter().map(|output| Output {
+                sink: get_sink(&output, config),
+                settings: output
+            }).collect()
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub async fn setup(&self) {
+        for output in &self.outputs {
+            info!("Events output '{}' of type {} in {} format", output.settings.name, output.settings.kind, output.settings.format);
+            if let Err(e) = output.sink.setup().await {
+                error!("Unable to set up output '{}', error: {}", output.settings.name, e);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub async fn send(&self, document: &dyn Document) {
+        let json = document.get_json();
+        let mut ecs = None;
+        for output in self.outputs.iter().filter(|output| output.matches(&json)) {
+            let document: &dyn Document = match output.settings.schema.as_str() {
+                "ecs" => ecs.get_or_insert_with(|| ecs::EcsDocument::new(document)),
+                _ => document
+            };
+            if let Err(e) = output.sink.write(document).await {
+                error!("Unable to write event {} to output '{}', error: {}", document.get_id(), output.settings.name, e);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub async fn flush(&self) {
+        for output in &self.outputs {
+            if let Err(e) = output.sink.flush().await {
+                error!("Unable to flush output '{}', error: {}", output.settings.name, e);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the names of the outputs
+    pub fn get_names(&self) -> Vec<String> {
+        self.outputs.iter().map(|output| output.settings.name.clone()).collect()
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn reload(&self, config: &config::Config) {
+        for output in &self.outputs {
+            output.sink.reload(config);
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the address and the error of each output that became unreachable since the last call
+    pub fn take_errors(&self) -> Vec<(String, String)> {
+        self.outputs.iter().filter_map(|output| output.sink.take_error()
+            .map(|error| (output.settings.address.clone(), error))).collect()
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the number of events waiting for delivery and the dropped ones of all outputs,
+    // None if no output holds events
+    pub fn get_status(&self) -> Option<(usize, u64)> {
+        self.outputs.iter().filter_map(|output| output.sink.get_status())
+            .reduce(|(pending, dropped), (output_pending, output_dropped)| (pending + output_pending, dropped + output_dropped))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{start_server_with, TestDocument};
+    use std::env;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_config(outputs: &str) -> config::Config {
+        let path = format!("test_outputs_{}.yml", outputs.len());
+        fs::write(&path, format!("events:\n  outputs: {}\nmonitor:\n  - path: /tmp\nlog:\n  file: test.log\n  level: info\n", outputs)).unwrap();
+        let config = config::Config::load(&path, env::consts::OS);
+        fs::remove_file(&path).unwrap();
+        config.ok().unwrap()
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_json() {
+        let document = TestDocument::new("1234", json!({ "kind": "CREATE", "file": "/etc/hosts" }));
+        assert_eq!(document.format_json(), "{\"file\":\"/etc/hosts\",\"id\":\"1234\",\"kind\":\"CREATE\"}");
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_index_name() {
+        let current_date = OffsetDateTime::now_utc();
+        assert_eq!(get_index_name("fim"), format!("fim-{}-{}-{}", current_date.year(), current_date.month() as u8, current_date.day()));
+        assert_eq!(get_index_name("ecs"), format!("fim-ecs-{}-{}-{}", current_date.year(), current_date.month() as u8, current_date.day()));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send() {
+        let config = create_test_config("[
+    {name: all, type: file, file: test_outputs/all.json},
+    {name: writes, type: file, file: test_outputs/writes.json, filter: {kinds: [WRITE]}},
+    {name: etc, type: file, file: test_outputs/etc.json, filter: {labels: [etc, passwd]}},
+    {name: siem, type: file, file: test_outputs/siem.log, format: cef},
+    {name: ecs, type: file, file: test_outputs/ecs.json, schema: ecs},
+    {name: broken, type: file, file: test_outputs/missing/folder/broken.json}]");
+        let outputs = Outputs::new(&config);
+        assert_eq!(outputs.get_names(), vec!["all", "writes", "etc", "siem", "ecs", "broken"]);
+        outputs.setup().await;
+        fs::remove_dir_all("test_outputs/missing").unwrap();
+
+        // A failing output doesn't stop the rest
+        outputs.send(&TestDocument::new("1", json!({ "kind": "CREATE", "labels": ["etc"] }))).await;
+        outputs.send(&TestDocument::new("2", json!({ "kind": "WRITE", "labels": ["bin"] }))).await;
+        outputs.send(&TestDocument::new("3", json!({ "kind": "HEARTBEAT" }))).await;
+        outputs.flush().await;
+        let read = |name: &str| fs::read_to_string(format!("test_outputs/{}.json", name)).unwrap().lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].as_str().unwrap().to_string()).collect::<Vec<String>>();
+        assert_eq!(read("all"), vec!["1", "2", "3"]);
+        assert_eq!(read("writes"), vec!["2"]);
+        assert_eq!(read("etc"), vec!["1"]);
+        let siem = fs::read_to_string("test_outputs/siem.log").unwrap();
+        assert_eq!(siem.lines().count(), 3);
+        assert!(siem.lines().all(|line| line.starts_with("CEF:0|Achiefs|FIM|")));
+        let ecs = fs::read_to_string("test_outputs/ecs.json").unwrap().lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["event"]["id"].as_str().unwrap().to_string()).collect::<Vec<String>>();
+        assert_eq!(ecs, vec!["1", "2", "3"]);
+        fs::remove_dir_all("test_outputs").unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_elasticsearch_sink() {
+        let (address, server) = start_server_with(vec![(201, String::new()), (400, String::new())]);
+        let config = create_test_config(&format!("[{{type: elasticsearch, address: \"{}\", credentials: {{user: admin, password: secret}}}}]", address));
+        let outputs = Outputs::new(&config);
+        assert_eq!(outputs.get_names(), vec!["elasticsearch"]);
+        outputs.send(&TestDocument::new("1234", json!({ "kind": "CREATE" }))).await;
+
+        // Events not delivered without spool are reported
+        let sink = get_sink(&config.get_outputs()[0], &config);
+        let result = sink.write(&TestDocument::new("5678", json!({ "kind": "WRITE" }))).await;
+        assert_eq!(result, Err(String::from("HTTP status 400 Bad Request")));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with(&format!("POST /{}/_doc/1234 ", get_index_name("fim"))));
+        assert!(requests[0].to_lowercase().contains("authorization: basic"));
+        assert!(requests[0].ends_with("{\"kind\":\"CREATE\"}"));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_elasticsearch_outputs() {
+        let bulk = String::from("{\"errors\":false,\"items\":[{\"index\":{\"status\":201}}]}");
+        let (address1, server1) = start_server_with(vec![(200, String::new()), (200, bulk)]);
+        let (address2, server2) = start_server_with(vec![(200, String::new()), (401, String::new())]);
+        let mut config = create_test_config(&format!("[
+    {{name: first, type: elasticsearch, address: \"{}\", credentials: {{user: admin, password: secret}}}},
+    {{name: second, type: elasticsearch, schema: ecs, address: \"{}\", credentials: {{user: admin, password: wrong}}}}]", address1, address2));
+        config.spool_dir = String::from("test_outputs_spool");
+        config.batch_max_events = 1;
+        config.retry_max_attempts = 1;
+        let outputs = Outputs::new(&config);
+        outputs.setup().await;
+        outputs.send(&TestDocument::new("1", json!({ "kind": "CREATE" }))).await;
+        let requests1 = server1.join().unwrap();
+        let requests2 = server2.join().unwrap();
+        assert!(requests1[0].starts_with("PUT /_template/fim "));
+        assert!(requests1[1].starts_with("POST /_bulk "));
+        assert!(requests1[1].contains(&format!("\"_index\":\"{}\"", get_index_name("fim"))));
+        // Events of the ecs schema have their own template and indexes
+        assert!(requests2[0].starts_with("PUT /_template/fim-ecs "));
+        assert!(requests2[1].contains(&format!("\"_index\":\"{}\"", get_index_name("ecs"))));
+
+        // Each output keeps its own spool and endpoint state
+        assert_eq!(fs::read_dir("test_outputs_spool/first").unwrap().count(), 0);
+        assert_eq!(fs::read_dir("test_outputs_spool/second").unwrap().count(), 1);
+        assert_eq!(outputs.get_status(), Some((1, 0)));
+        assert_eq!(outputs.take_errors(), vec![(address2, String::from("HTTP status 401 Unauthorized"))]);
+        assert!(outputs.take_errors().is_empty());
+        fs::remove_dir_all("test_outputs_spool").unwrap();
+    }
+}
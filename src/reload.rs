@@ -0,0 +1,229 @@
+// Copyright (C) 2021, Achiefs.
+
+// To read the modification time of the configuration file
+use std::fs;
+use std::time::SystemTime;
+// To share the reload request with the signal handler
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+// To log the program process
+use log::*;
+
+use crate::config;
+
+// ----------------------------------------------------------------------------
+
+// Detect when the configuration has to be loaded again, on SIGHUP or when its file is modified
+pub struct ConfigWatch {
+    path: String,
+    mtime: Option<SystemTime>,
+    hangup: Arc<AtomicBool>
+}
+
+impl ConfigWatch {
+    pub fn new(path: &str) -> Self {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup)) {
+            error!("Unable to handle SIGHUP signal, error: {:?}", e);
+        }
+        ConfigWatch {
+            path: String::from(path),
+            mtime: get_mtime(path),
+            hangup
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Check if a reload was requested since the last call
+    pub fn requested(&mut self) -> bool {
+        let hangup = self.hangup.swap(false, Ordering::Relaxed);
+        if hangup {
+            info!("SIGHUP received, reloading configuration");
+        }
+        let mtime = get_mtime(&self.path);
+        let modified = mtime.is_some() && mtime != self.mtime;
+        if modified {
+            info!("Configuration file '{}' modified, reloading it", self.path);
+        }
+        self.mtime = mtime;
+        hangup || modified
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn get_mtime(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// ----------------------------------------------------------------------------
+
+// Differences between the monitor entries of two configurations, paths identify each entry
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    // Indexes in the new configuration
+    pub added: Vec<usize>,
+    pub modified: Vec<usize>,
+    pub removed: Vec<String>
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+pub fn diff_monitor(old: &config::Config, new: &config::Config) -> Changes {
+    let mut changes = Changes::default();
+    for (index, entry) in new.monitor.iter().enumerate() {
+        match old.monitor.iter().find(|previous| previous.path == entry.path) {
+            Some(previous) if previous != entry => changes.modified.push(index),
+            Some(_) => (),
+            None => changes.added.push(index)
+        }
+    }
+    changes.removed = old.monitor.iter()
+        .filter(|previous| ! new.monitor.iter().any(|entry| entry.path == previous.path))
+        .map(|previous| previous.path.clone()).collect();
+    changes
+}
+
+// ----------------------------------------------------------------------------
+
+// Settings used to set up outputs and state on start are kept from the running configuration,
+// the names of the changed ones are returned
+pub fn keep_restart_settings(old: &config::Config, new: &mut config::Config) -> Vec<&'static str> {
+    // Command line overrides are applied again so only changes of the file are compared
+    if let Some(level) = &old.log_level_override {
+        new.log_level = level.clone();
+        new.log_level_override = Some(level.clone());
+    }
+    let mut changed = Vec::new();
+    let mut keep = |name: &'static str, old: &String, new: &mut String| {
+        if old != new {
+            changed.push(name);
+            *new = old.clone();
+        }
+    };
+    keep("events.destination", &old.events_destination, &mut new.events_destination);
+    keep("events.file", &old.events_file, &mut new.events_file);
+    keep("events.endpoint.address", &old.endpoint_address, &mut new.endpoint_address);
+    keep("events.endpoint.credentials.user", &old.endpoint_user, &mut new.endpoint_user);
+    keep("events.endpoint.credentials.password", &old.endpoint_pass, &mut new.endpoint_pass);
+    keep("events.spool.dir", &old.spool_dir, &mut new.spool_dir);
+    keep("events.spool.policy", &old.spool_policy, &mut new.spool_policy);
+    keep("baseline.file", &old.baseline_file, &mut new.baseline_file);
+    keep("baseline.approved_file", &old.approved_file, &mut new.approved_file);
+    keep("log.file", &old.log_file, &mut new.log_file);
+    keep("log.level", &old.log_level, &mut new.log_level);
+    if old.spool_max_size != new.spool_max_size {
+        changed.push("events.spool.max_size");
+        new.spool_max_size = old.spool_max_size;
+    }
+    if old.batch_max_events != new.batch_max_events {
+        changed.push("events.batch.max_events");
+        new.batch_max_events = old.batch_max_events;
+    }
+    if old.batch_max_bytes != new.batch_max_bytes {
+        changed.push("events.batch.max_bytes");
+        new.batch_max_bytes = old.batch_max_bytes;
+    }
+    if old.insecure != new.insecure {
+        changed.push("events.endpoint.insecure");
+        new.insecure = old.insecure;
+    }
+    if old.outputs != new.outputs {
+        changed.push("events.outputs");
+        new.outputs = old.outputs.clone();
+    }
+    changed
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_config(yaml: &str) -> config::Config {
+        let mut config = config::Config::new(env::consts::OS);
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        config
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_config_watch() {
+        let filename = "test_config_watch.yml";
+        fs::write(filename, "nodename: test").unwrap();
+        let mut watch = ConfigWatch::new(filename);
+        assert!(!watch.requested());
+
+        let file = fs::File::options().write(true).open(filename).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert!(watch.requested());
+        assert!(!watch.requested());
+
+        watch.hangup.store(true, Ordering::Relaxed);
+        assert!(watch.requested());
+        assert!(!watch.requested());
+
+        // Missing file keeps the loaded configuration
+        fs::remove_file(filename).unwrap();
+        assert!(!watch.requested());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_diff_monitor() {
+        let old = create_test_config("[{path: /etc}, {path: /bin, labels: [bin]}, {path: /tmp}]");
+        let new = create_test_config("[{path: /etc}, {path: /usr}, {path: /bin, labels: [bin], ignore: [\".swp\"]}]");
+        assert_eq!(diff_monitor(&old, &new), Changes {
+            added: vec![1], modified: vec![2], removed: vec![String::from("/tmp")]
+        });
+        assert!(diff_monitor(&old, &old.clone()).is_empty());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_keep_restart_settings() {
+        let old = create_test_config("[{path: /etc}]");
+        let mut new = old.clone();
+        assert!(keep_restart_settings(&old, &mut new).is_empty());
+
+        new.events_file = String::from("other.json");
+        new.log_level = String::from("debug");
+        new.insecure = !old.insecure;
+        new.nodename = String::from("other");
+        assert_eq!(keep_restart_settings(&old, &mut new), vec!["events.file", "log.level", "events.endpoint.insecure"]);
+        assert_eq!(new.events_file, old.events_file);
+        assert_eq!(new.log_level, old.log_level);
+        assert_eq!(new.insecure, old.insecure);
+        assert_eq!(new.nodename, String::from("other"));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_keep_restart_settings_override() {
+        let mut old = create_test_config("[{path: /etc}]");
+        old.log_level = String::from("debug");
+        old.log_level_override = Some(String::from("debug"));
+        let mut new = create_test_config("[{path: /etc}]");
+        new.log_level = String::from("info");
+        assert!(keep_restart_settings(&old, &mut new).is_empty());
+        assert_eq!(new.log_level, String::from("debug"));
+        assert_eq!(new.log_level_override, Some(String::from("debug")));
+    }
+}
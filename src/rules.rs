@@ -0,0 +1,242 @@
+// Copyright (C) 2021, Achiefs.
+
+// To implement Display method
+use std::fmt;
+// To manage paths
+use std::path::Path;
+// To read rules from the configuration
+use serde::de::{self, Deserialize, Deserializer};
+use serde_yaml::Value;
+// To match paths with patterns
+use glob::{Pattern, MatchOptions};
+use regex::Regex;
+
+// ----------------------------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub enum Kind {
+    // Substring of the file name, default when the rule is a plain string
+    Contains(String),
+    // Shell pattern matched against the full path, patterns without separators
+    // match any component of it so "*.log" or "cache" are found at any depth
+    Glob(Pattern),
+    Regex(Regex)
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub kind: Kind,
+    pub negated: bool
+}
+
+impl Rule {
+    // Build a rule of the given type, a leading '!' in the pattern negates it
+    pub fn new(kind: &str, value: &str) -> Result<Self, String> {
+        let (negated, pattern) = match value.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, value)
+        };
+
+        let kind = match kind {
+            "contains" => Kind::Contains(String::from(pattern)),
+            "glob" => match Pattern::new(pattern) {
+                Ok(glob) => Kind::Glob(glob),
+                Err(e) => return Err(format!("Invalid glob '{}': {}", pattern, e))
+            },
+            "regex" => match Regex::new(pattern) {
+                Ok(regex) => Kind::Regex(regex),
+                Err(e) => return Err(format!("Invalid regex '{}': {}", pattern, e))
+            },
+            _ => return Err(format!("Unknown rule type '{}', available: contains, glob, regex", kind))
+        };
+        Ok(Rule { kind, negated })
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let filename = match path.file_name() {
+            Some(name) => name.to_str().unwrap_or(""),
+            None => ""
+        };
+        match &self.kind {
+            Kind::Contains(value) => filename.contains(value.as_str()),
+            Kind::Glob(glob) => {
+                let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+                match glob.as_str().contains('/') || glob.as_str().contains('\\') {
+                    true => glob.matches_path_with(path, options),
+                    false => path.components().any(|component| glob.matches_with(&component.as_os_str().to_string_lossy(), options))
+                }
+            },
+            Kind::Regex(regex) => regex.is_match(path.to_str().unwrap_or(""))
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let negation = if self.negated { "!" } else { "" };
+        match &self.kind {
+            Kind::Contains(value) => write!(f, "contains:{}{}", negation, value),
+            Kind::Glob(glob) => write!(f, "glob:{}{}", negation, glob),
+            Kind::Regex(regex) => write!(f, "regex:{}{}", negation, regex)
+        }
+    }
+}
+
+// Rules are read like ".swp", {contains: ".swp"}, {glob: "**/*.log"} or {regex: "^/tmp/.*"}
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (kind, value) = match Value::deserialize(deserializer)? {
+            Value::String(value) => (String::from("contains"), value),
+            Value::Mapping(map) if map.len() == 1 => {
+                let (key, value) = map.into_iter().next().unwrap();
+                match (key, value) {
+                    (Value::String(key), Value::String(value)) => (key, value),
+                    _ => return Err(de::Error::custom("rule type and pattern must be strings"))
+                }
+            },
+            _ => return Err(de::Error::custom("expected a string or a single rule like 'glob: pattern'"))
+        };
+        Rule::new(&kind, &value).map_err(de::Error::custom)
+    }
+}
+
+// Compiled patterns can't be compared so their source is used
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Check if a path has to be ignored, last matching rule of each list decides
+// so negated rules could revert previous ones
+pub fn is_ignored(ignore: &[Rule], include: &[Rule], path: &Path) -> bool {
+    let ignored = last_match(ignore, path);
+    let included = include.is_empty() || last_match(include, path);
+    ignored || ! included
+}
+
+// ----------------------------------------------------------------------------
+
+fn last_match(rules: &[Rule], path: &Path) -> bool {
+    match rules.iter().rev().find(|rule| rule.matches(path)) {
+        Some(rule) => ! rule.negated,
+        None => false
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_rules(yaml: &str) -> Vec<Rule> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_new() {
+        assert_eq!(Rule::new("contains", ".swp").unwrap().to_string(), "contains:.swp");
+        let rule = Rule::new("glob", "!*.log").unwrap();
+        assert!(rule.negated);
+        assert_eq!(rule.to_string(), "glob:!*.log");
+        assert_eq!(Rule::new("regex", "^/tmp").unwrap().to_string(), "regex:^/tmp");
+        assert!(Rule::new("bad", "x").is_err());
+        assert!(Rule::new("glob", "[").is_err());
+        assert!(Rule::new("regex", "(").is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_deserialize() {
+        let rules = create_test_rules("[\".swp\", contains: \"~\", glob: \"!*.log\", regex: \"^/tmp\"]");
+        let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+        assert_eq!(rules, vec!["contains:.swp", "contains:~", "glob:!*.log", "regex:^/tmp"]);
+        assert!(serde_yaml::from_str::<Rule>("bad: \"x\"").is_err());
+        assert!(serde_yaml::from_str::<Rule>("glob: \"[\"").is_err());
+        assert!(serde_yaml::from_str::<Rule>("[1]").is_err());
+        assert!(serde_yaml::from_str::<Rule>("{glob: a, regex: b}").is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_matches_contains() {
+        let rule = Rule::new("contains", ".swp").unwrap();
+        assert!(rule.matches(Path::new("/tmp/file.swp")));
+        assert!(rule.matches(Path::new("/tmp/my.swpfile")));
+        // Only the file name is checked
+        assert!(!rule.matches(Path::new("/tmp/.swp/file")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_matches_glob() {
+        let rules = create_test_rules("[glob: \"*.swp\", glob: \"/var/log/**/*.gz\", glob: \"/tmp/*/cache\"]");
+        assert!(rules[0].matches(Path::new("/tmp/file.swp")));
+        assert!(!rules[0].matches(Path::new("/tmp/my.swpfile")));
+        assert!(rules[1].matches(Path::new("/var/log/apt/history.log.1.gz")));
+        assert!(rules[1].matches(Path::new("/var/log/syslog.gz")));
+        assert!(!rules[1].matches(Path::new("/var/log/syslog")));
+        assert!(rules[2].matches(Path::new("/tmp/user/cache")));
+        assert!(!rules[2].matches(Path::new("/tmp/user/dir/cache")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_matches_glob_full_path() {
+        // Patterns without separators match any directory of the path too
+        let rules = create_test_rules("[glob: \"cache\", glob: \"*.d\", glob: \"/etc/*.conf\"]");
+        assert!(rules[0].matches(Path::new("/var/cache")));
+        assert!(rules[0].matches(Path::new("/var/cache/apt/pkgcache.bin")));
+        assert!(!rules[0].matches(Path::new("/var/caches/file")));
+        assert!(rules[1].matches(Path::new("/etc/cron.d/backup")));
+        assert!(!rules[1].matches(Path::new("/etc/cron.daily/backup")));
+        // Anchored patterns have to match the whole path
+        assert!(rules[2].matches(Path::new("/etc/app.conf")));
+        assert!(!rules[2].matches(Path::new("/etc/app/app.conf")));
+        assert!(!rules[2].matches(Path::new("/backup/etc/app.conf")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_matches_regex() {
+        let rule = Rule::new("regex", "^/tmp/[0-9]+$").unwrap();
+        assert!(rule.matches(Path::new("/tmp/1234")));
+        assert!(!rule.matches(Path::new("/tmp/1234a")));
+        assert!(!rule.matches(Path::new("/var/tmp/1234")));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_is_ignored() {
+        let ignore = create_test_rules("[glob: \"*.log\", glob: \"!important.log\"]");
+        assert!(is_ignored(&ignore, &[], Path::new("/tmp/file.log")));
+        assert!(!is_ignored(&ignore, &[], Path::new("/tmp/important.log")));
+        assert!(!is_ignored(&ignore, &[], Path::new("/tmp/file.txt")));
+
+        let include = create_test_rules("[glob: \"*.conf\", regex: \"!secret\"]");
+        let ignore = create_test_rules("[\"~\"]");
+        assert!(!is_ignored(&ignore, &include, Path::new("/etc/app.conf")));
+        assert!(is_ignored(&ignore, &include, Path::new("/etc/app.conf~")));
+        assert!(is_ignored(&ignore, &include, Path::new("/etc/passwd")));
+        assert!(is_ignored(&ignore, &include, Path::new("/etc/secret/app.conf")));
+
+        assert!(!is_ignored(&[], &[], Path::new("/tmp/file")));
+    }
+}
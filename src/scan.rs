@@ -0,0 +1,356 @@
+// Copyright (C) 2021, Achiefs.
+
+// To manage time intervals
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
+// To handle JSON objects
+use serde_json::{json, Value};
+// To manage unique event identifier
+use uuid::Uuid;
+// To get own process ID
+use std::process;
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::output::Document;
+
+// ----------------------------------------------------------------------------
+
+// Parsed cron expression in the five fields format "minute hour day month weekday"
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    any_day: bool,
+    any_weekday: bool
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 fields in cron expression '{}'", expression));
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            // Sunday could be set as 0 or 7
+            weekdays: parse_field(fields[4], 0, 7)?.iter().map(|day| day % 7).collect(),
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*"
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Check if the given date matches the expression, days use the standard cron rule
+    // where any of day or weekday have to match when both are restricted
+    pub fn matches(&self, date: OffsetDateTime) -> bool {
+        let day = self.days.contains(&(date.day() as u32));
+        let weekday = self.weekdays.contains(&(date.weekday().number_days_from_sunday() as u32));
+        let day_match = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday
+        };
+        self.minutes.contains(&(date.minute() as u32)) &&
+            self.hours.contains(&(date.hour() as u32)) &&
+            self.months.contains(&(date.month() as u32)) &&
+            day_match
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Parse a single cron field with lists, ranges and steps like "1,5-10,*/15"
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, String> {
+    let mut values = Vec::new();
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(value) if value > 0 => (range, value),
+                _ => return Err(format!("Invalid step in cron field '{}'", field))
+            },
+            None => (item, 1)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, field)?, parse_value(end, field)?),
+                None => {
+                    let value = parse_value(range, field)?;
+                    // A single value with step means from that value to the end
+                    if item.contains('/') { (value, max) } else { (value, value) }
+                }
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("Value out of range {}-{} in cron field '{}'", min, max, field));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+fn parse_value(value: &str, field: &str) -> Result<u32, String> {
+    value.parse::<u32>().map_err(|_| format!("Invalid value '{}' in cron field '{}'", value, field))
+}
+
+// ----------------------------------------------------------------------------
+
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Cron)
+}
+
+// ----------------------------------------------------------------------------
+
+// Scan schedule of each monitor entry and the last time it was run
+pub struct Scheduler {
+    entries: Vec<(usize, Schedule, Instant, i64)>
+}
+
+impl Scheduler {
+    pub fn new(config: &config::Config) -> Self {
+        let mut entries = Vec::new();
+        for index in 0..config.monitor.len() {
+            let path = &config.monitor[index].path;
+            let (interval, schedule) = config.get_scan(index);
+            if ! schedule.is_empty() {
+                match Cron::parse(&schedule) {
+                    Ok(cron) => {
+                        info!("Scheduled scan of '{}' with: {}", path, schedule);
+                        entries.push((index, Schedule::Cron(cron), Instant::now(), 0));
+                    },
+                    Err(e) => error!("Scan of '{}' not scheduled, error: {}", path, e)
+                }
+            }else if interval > 0 {
+                info!("Scheduled scan of '{}' every {} seconds", path, interval);
+                entries.push((index, Schedule::Interval(Duration::from_secs(interval)), Instant::now(), 0));
+            }
+        }
+        Scheduler { entries }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get monitor entries whose scan should be run now
+    pub fn due(&mut self) -> Vec<usize> {
+        self.due_at(OffsetDateTime::now_utc(), Instant::now())
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get monitor entries whose scan should be run at the given date and instant
+    fn due_at(&mut self, now: OffsetDateTime, instant: Instant) -> Vec<usize> {
+        // Cron expressions are evaluated once per minute
+        let minute = now.unix_timestamp() / 60;
+        let mut due = Vec::new();
+        for (index, schedule, last_run, last_minute) in self.entries.iter_mut() {
+            let run = match schedule {
+                Schedule::Interval(interval) => instant.saturating_duration_since(*last_run) >= *interval,
+                Schedule::Cron(cron) => *last_minute != minute && cron.matches(now)
+            };
+            if run {
+                *last_run = instant;
+                *last_minute = minute;
+                due.push(*index);
+            }
+        }
+        due
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Summary of a finished scan over a monitor path
+pub struct ScanSummary {
+    pub id: String,
+    pub timestamp: String,
+    pub hostname: String,
+    pub nodename: String,
+    pub version: String,
+    pub path: String,
+    pub labels: Vec<String>,
+    pub scanned: usize,
+    pub created: usize,
+    pub written: usize,
+    pub removed: usize,
+    pub chmod: usize,
+    pub duration: u128,
+    pub pid: u32,
+    pub system: String
+}
+
+impl ScanSummary {
+    pub fn new(config: &config::Config, index: usize, scanned: usize, duration: Duration) -> Self {
+        ScanSummary {
+            id: format!("{}", Uuid::new_v4()),
+            timestamp: format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis()),
+            hostname: gethostname::gethostname().into_string().unwrap(),
+            nodename: config.nodename.clone(),
+            version: String::from(config::VERSION),
+            path: config.monitor[index].path.clone(),
+            labels: config.get_labels(index),
+            scanned,
+            created: 0,
+            written: 0,
+            removed: 0,
+            chmod: 0,
+            duration: duration.as_millis(),
+            pid: process::id(),
+            system: config.system.clone()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl Document for ScanSummary {
+    fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.clone(),
+            "hostname": self.hostname.clone(),
+            "node": self.nodename.clone(),
+            "pid": self.pid,
+            "version": self.version.clone(),
+            "labels": self.labels.clone(),
+            "kind": "SCAN",
+            "file": self.path.clone(),
+            "scanned": self.scanned,
+            "created": self.created,
+            "written": self.written,
+            "removed": self.removed,
+            "chmod": self.chmod,
+            "duration": self.duration as u64,
+            "system": self.system.clone()
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event;
+    use std::fs;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_config(yaml: &str) -> config::Config {
+        let mut config = config::Config::new(std::env::consts::OS);
+        config.monitor = serde_yaml::from_str(yaml).unwrap();
+        config
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(parse_field("*", 0, 5).unwrap(), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(parse_field("*/2", 0, 5).unwrap(), vec![0, 2, 4]);
+        assert_eq!(parse_field("1,3", 0, 5).unwrap(), vec![1, 3]);
+        assert_eq!(parse_field("1-3", 0, 5).unwrap(), vec![1, 2, 3]);
+        assert_eq!(parse_field("1-5/2", 0, 5).unwrap(), vec![1, 3, 5]);
+        assert_eq!(parse_field("2/2", 0, 5).unwrap(), vec![2, 4]);
+        assert!(parse_field("6", 0, 5).is_err());
+        assert!(parse_field("3-1", 0, 5).is_err());
+        assert!(parse_field("*/0", 0, 5).is_err());
+        assert!(parse_field("a", 0, 5).is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_cron_parse() {
+        assert!(Cron::parse("0 3 * * *").is_ok());
+        assert!(Cron::parse("*/15 * * * 1-5").is_ok());
+        assert_eq!(Cron::parse("* * * * 7").unwrap().weekdays, vec![0]);
+        assert!(Cron::parse("0 3 * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("").is_err());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_cron_matches() {
+        // 2022-06-01 03:00 UTC, it was a Wednesday
+        let date = OffsetDateTime::from_unix_timestamp(1654052400).unwrap();
+        assert!(Cron::parse("0 3 * * *").unwrap().matches(date));
+        assert!(Cron::parse("*/30 * * * *").unwrap().matches(date));
+        assert!(Cron::parse("0 3 * * 3").unwrap().matches(date));
+        assert!(!Cron::parse("0 3 * * 4").unwrap().matches(date));
+        assert!(!Cron::parse("1 3 * * *").unwrap().matches(date));
+        assert!(!Cron::parse("0 3 * 7 *").unwrap().matches(date));
+        // When day and weekday are restricted any of them matches
+        assert!(Cron::parse("0 3 15 * 3").unwrap().matches(date));
+        assert!(Cron::parse("0 3 1 * 4").unwrap().matches(date));
+        assert!(!Cron::parse("0 3 15 * 4").unwrap().matches(date));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scheduler() {
+        let config = create_test_config("- path: /tmp/\n  scan:\n    interval: 1\n- path: /etc\n  scan:\n    schedule: \"* * * * *\"\n- path: /bin/\n  scan:\n    schedule: \"bad\"");
+        let mut scheduler = Scheduler::new(&config);
+        assert_eq!(scheduler.entries.len(), 2);
+        // 2022-06-01 03:00:30 UTC
+        let date = OffsetDateTime::from_unix_timestamp(1654052430).unwrap();
+        let start = Instant::now();
+        // Cron entry runs once per minute
+        assert_eq!(scheduler.due_at(date, start), vec![1]);
+        assert!(scheduler.due_at(date + time::Duration::seconds(29), start).is_empty());
+        assert_eq!(scheduler.due_at(date + time::Duration::seconds(30), start), vec![1]);
+        // Interval entry runs when the interval passed since the last run
+        let later = start + Duration::from_millis(1100);
+        assert_eq!(scheduler.due_at(date + time::Duration::seconds(31), later), vec![0]);
+        assert!(scheduler.due_at(date + time::Duration::seconds(31), later + Duration::from_millis(500)).is_empty());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_scan_summary() {
+        let config = create_test_config("- path: /tmp/\n  labels: [\"tmp\"]");
+        let mut summary = ScanSummary::new(&config, 0, 10, Duration::from_millis(1500));
+        summary.written = 2;
+        assert_eq!(summary.path, String::from("/tmp/"));
+        assert_eq!(summary.labels, vec![String::from("tmp")]);
+        assert_eq!(summary.scanned, 10);
+        assert_eq!(summary.duration, 1500);
+
+        let json: serde_json::Value = serde_json::from_str(&summary.format_json()).unwrap();
+        assert_eq!(json["kind"], "SCAN");
+        assert_eq!(json["id"], summary.id);
+        assert_eq!(json["written"], 2);
+        assert_eq!(json["created"], 0);
+        assert_eq!(json["duration"], 1500);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_log_scan_summary() {
+        let filename = String::from("test_log_scan_summary.json");
+        let config = create_test_config("- path: /tmp/");
+        let summary = ScanSummary::new(&config, 0, 1, Duration::from_millis(1));
+        event::log_json(filename.clone(), summary.format_json()).unwrap();
+        let contents = fs::read_to_string(filename.clone()).unwrap();
+        assert_eq!(contents, format!("{}\n", summary.format_json()));
+        fs::remove_file(filename).unwrap();
+    }
+}
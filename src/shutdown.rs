@@ -0,0 +1,55 @@
+// Copyright (C) 2021, Achiefs.
+
+// To share the stop request with the signal handler
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+// To handle termination signals
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+// To log the program process
+use log::*;
+
+// ----------------------------------------------------------------------------
+
+// Stop request received with SIGTERM or SIGINT, a second signal stops the program at once
+pub struct Shutdown {
+    requested: Arc<AtomicBool>
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let requested = Arc::new(AtomicBool::new(false));
+        for signal in [SIGTERM, SIGINT] {
+            // Order matters, the forced exit only happens if the flag was already set
+            let registered = flag::register_conditional_shutdown(signal, 1, Arc::clone(&requested))
+                .and_then(|_| flag::register(signal, Arc::clone(&requested)));
+            if let Err(e) = registered {
+                error!("Unable to handle signal {}, error: {:?}", signal, e);
+            }
+        }
+        Shutdown { requested }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_shutdown() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.requested());
+        shutdown.requested.store(true, Ordering::Relaxed);
+        assert!(shutdown.requested());
+    }
+}
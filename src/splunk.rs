@@ -0,0 +1,403 @@
+// Copyright (C) 2021, Achiefs.
+
+// To build HTTP Event Collector requests
+use serde_json::{json, to_string, Value};
+use reqwest::header;
+// To keep events in the spool until they are delivered
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::event::{self, Delivery};
+use crate::backoff;
+use crate::spool;
+use crate::format;
+use crate::output::{Document, EventSink, SinkFuture};
+
+// Seconds to wait for the acknowledgement of a request before sending its events again
+const ACK_TIMEOUT: u64 = 300;
+const CHANNEL_HEADER: &str = "X-Splunk-Request-Channel";
+const EVENT_PATH: &str = "/services/collector/event";
+
+// ----------------------------------------------------------------------------
+
+// Request sent to the collector waiting for indexer acknowledgement, its events are kept
+// in the spool until they are indexed
+struct Ack {
+    id: u64,
+    paths: Vec<PathBuf>,
+    sent: Instant
+}
+
+// ----------------------------------------------------------------------------
+
+// Splunk HTTP Event Collector, events are sent in batches authenticated with the token.
+// They are kept in the spool folder of the output until the collector accepts them, or
+// until they are indexed when acknowledgements are used
+pub struct SplunkSink {
+    settings: config::Output,
+    // Batch and spool settings
+    config: config::Config,
+    policy: Mutex<backoff::Policy>,
+    spool: Mutex<Option<spool::Spool>>,
+    acks: Mutex<Vec<Ack>>
+}
+
+impl SplunkSink {
+    pub fn new(settings: &config::Output, config: &config::Config) -> Self {
+        SplunkSink {
+            settings: settings.clone(),
+            config: config.clone(),
+            policy: Mutex::new(backoff::Policy::new(config, settings)),
+            spool: Mutex::new(None),
+            acks: Mutex::new(Vec::new())
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Wrap the event with the metadata of the collector
+    pub fn get_event(&self, document: &dyn Document) -> Value {
+        let json = document.get_json();
+        let time = format::get_time(&json).unix_timestamp_nanos() / 1_000_000;
+        let mut event = json!({
+            "time": time as f64 / 1000.0,
+            "host": json["hostname"],
+            "source": self.settings.source,
+            "sourcetype": self.settings.sourcetype,
+            "event": match self.settings.format.as_str() {
+                "json" => serde_json::from_str::<Value>(&document.format_json()).unwrap(),
+                format => json!(format::get_line(document, format))
+            }
+        });
+        if ! self.settings.index.is_empty() {
+            event["index"] = json!(self.settings.index);
+        }
+        if event["host"].is_null() {
+            event.as_object_mut().unwrap().remove("host");
+        }
+        event
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Send a request to the collector, the body of successful responses is returned.
+    // Server errors and wrong token or address responses are failed so events are kept
+    async fn post(&self, path: &str, body: String) -> (Delivery, Option<Value>) {
+        let url = format!("{}{}", self.settings.address, path);
+        let client = event::get_client(self.settings.insecure);
+        let policy = self.policy.lock().unwrap().clone();
+        let response = backoff::send(&policy, "Splunk request", || {
+            let mut request = client.post(&url)
+                .header(header::AUTHORIZATION, format!("Splunk {}", self.settings.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if ! self.settings.channel.is_empty() {
+                request = request.header(CHANNEL_HEADER, &self.settings.channel);
+            }
+            request.send()
+        }).await;
+        match response {
+            Ok(response) => {
+                let status = response.status();
+                let json = response.json::<Value>().await.unwrap_or_default();
+                match event::get_delivery(status, &policy, format!("HTTP status {}, {}", status, json)) {
+                    Delivery::Delivered => (Delivery::Delivered, Some(json)),
+                    delivery => (delivery, None)
+                }
+            },
+            Err(e) => (Delivery::Failed(e.to_string()), None)
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the first spooled events not waiting for acknowledgement up to the batch limits,
+    // one at least
+    fn get_batch(&self) -> Vec<(PathBuf, String)> {
+        let sent: HashSet<PathBuf> = self.acks.lock().unwrap().iter().flat_map(|ack| ack.paths.clone()).collect();
+        let mut spool = self.spool.lock().unwrap();
+        let spool = match spool.as_mut() {
+            Some(spool) => spool,
+            None => return Vec::new()
+        };
+        let (max_events, max_bytes) = self.config.get_batch(&self.settings);
+        let mut batch = Vec::new();
+        let mut bytes = 0;
+        for path in spool.pending().into_iter().filter(|path| ! sent.contains(path)) {
+            let event = match spool::read(&path) {
+                Some((_, data)) => data.to_string(),
+                None => {
+                    error!("Spooled event '{}' rejected, unable to read it", path.display());
+                    spool.reject(&path);
+                    continue;
+                }
+            };
+            if batch.len() >= max_events || (! batch.is_empty() && bytes + event.len() as u64 > max_bytes) {
+                break;
+            }
+            bytes += event.len() as u64;
+            batch.push((path, event));
+        }
+        batch
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Send the spooled events in batches, the ones of a failed batch are kept for the next flush
+    // and the ones rejected by the collector are moved to the rejected folder of the spool
+    async fn send_events(&self) -> Result<(), String> {
+        loop {
+            let (paths, events): (Vec<PathBuf>, Vec<String>) = self.get_batch().into_iter().unzip();
+            if paths.is_empty() {
+                return Ok(());
+            }
+            match self.post(EVENT_PATH, events.join("\n")).await {
+                (Delivery::Delivered, response) => {
+                    let id = response.and_then(|json| json["ackId"].as_u64());
+                    match (self.settings.channel.is_empty(), id) {
+                        (false, Some(id)) => self.acks.lock().unwrap().push(Ack { id, paths, sent: Instant::now() }),
+                        _ => self.remove(&paths)
+                    }
+                },
+                (Delivery::Rejected(e), _) => {
+                    error!("Events rejected by Splunk collector, {} events moved to the rejected folder, error: {}", paths.len(), e);
+                    if let Some(spool) = self.spool.lock().unwrap().as_mut() {
+                        paths.iter().for_each(|path| spool.reject(path));
+                    }
+                },
+                (Delivery::Failed(e), _) => return Err(format!("Unable to send events to Splunk collector, error: {}", e))
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn remove(&self, paths: &[PathBuf]) {
+        if let Some(spool) = self.spool.lock().unwrap().as_mut() {
+            paths.iter().for_each(|path| spool.remove(path));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Check the acknowledgements of sent requests, indexed events are removed from the spool
+    // and the ones not indexed in ACK_TIMEOUT seconds are sent again
+    async fn check_acks(&self) {
+        let ids: Vec<u64> = self.acks.lock().unwrap().iter().map(|ack| ack.id).collect();
+        if ids.is_empty() {
+            return;
+        }
+        let acked = match self.post("/services/collector/ack", to_string(&json!({ "acks": ids })).unwrap()).await {
+            (Delivery::Delivered, Some(json)) => json["acks"].clone(),
+            (Delivery::Rejected(e), _) | (Delivery::Failed(e), _) => {
+                debug!("Unable to check Splunk acknowledgements, error: {}", e);
+                Value::Null
+            },
+            _ => Value::Null
+        };
+        let (indexed, waiting): (Vec<Ack>, Vec<Ack>) = self.acks.lock().unwrap().drain(..)
+            .partition(|ack| acked[ack.id.to_string()] == json!(true));
+        let (expired, waiting): (Vec<Ack>, Vec<Ack>) = waiting.into_iter()
+            .partition(|ack| ack.sent.elapsed() >= Duration::from_secs(ACK_TIMEOUT));
+        *self.acks.lock().unwrap() = waiting;
+        self.remove(&indexed.into_iter().flat_map(|ack| ack.paths).collect::<Vec<PathBuf>>());
+        if ! expired.is_empty() {
+            warn!("Splunk requests not acknowledged in {} seconds, sending them again: {}", ACK_TIMEOUT, expired.len());
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl EventSink for SplunkSink {
+    fn setup(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            info!("Splunk collector: {}, index: '{}', sourcetype: {}", self.settings.address, self.settings.index, self.settings.sourcetype);
+            *self.spool.lock().unwrap() = spool::open(&self.config, &self.settings);
+            Ok(())
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Events are sent directly if the spool is not in use
+    fn write<'a>(&'a self, document: &'a dyn Document) -> SinkFuture<'a> {
+        Box::pin(async {
+            let event = self.get_event(document);
+            let url = format!("{}{}", self.settings.address, EVENT_PATH);
+            let spooled = self.spool.lock().unwrap().as_mut().map(|spool| (spool.push(&url, &event), spool.is_batch_ready()));
+            match spooled {
+                Some((true, ready)) => match ready {
+                    true => self.send_events().await,
+                    false => Ok(())
+                },
+                Some((false, _)) => Err(String::from("event dropped, the spool is full or not writable")),
+                None => match self.post(EVENT_PATH, event.to_string()).await {
+                    (Delivery::Delivered, _) => Ok(()),
+                    (Delivery::Rejected(e), _) | (Delivery::Failed(e), _) => Err(e)
+                }
+            }
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn flush(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            self.check_acks().await;
+            self.send_events().await
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn reload(&self, config: &config::Config) {
+        *self.policy.lock().unwrap() = backoff::Policy::new(config, &self.settings);
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn get_status(&self) -> Option<(usize, u64)> {
+        self.spool.lock().unwrap().as_ref().map(|spool| spool.get_status())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{start_server_with, TestDocument};
+    use std::fs;
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_document(id: &str) -> TestDocument {
+        TestDocument::new(id, json!({ "timestamp": "1700000000123", "hostname": "web01", "kind": "CREATE", "file": "/etc/hosts" }))
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the number of spooled events and acknowledgements waiting
+    fn get_status(sink: &SplunkSink) -> (usize, usize) {
+        (sink.get_status().unwrap().0, sink.acks.lock().unwrap().len())
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_sink(address: &str, channel: &str, spool_dir: &str) -> SplunkSink {
+        let mut settings = config::Output::new("splunk", "splunk");
+        settings.address = String::from(address);
+        settings.token = String::from("secret");
+        settings.index = String::from("security");
+        settings.sourcetype = String::from("_json");
+        settings.source = String::from("fim");
+        settings.channel = String::from(channel);
+        let mut config = config::Config::new(std::env::consts::OS);
+        config.batch_max_events = 2;
+        config.retry_max_attempts = 1;
+        config.spool_dir = String::from(spool_dir);
+        SplunkSink::new(&settings, &config)
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_event() {
+        let mut sink = create_test_sink("http://127.0.0.1", "", "test_splunk_get_event");
+        let document = create_test_document("1");
+        assert_eq!(to_string(&sink.get_event(&document)).unwrap(), "{\"event\":{\"file\":\"/etc/hosts\",\"hostname\":\"web01\",\"id\":\"1\",\
+            \"kind\":\"CREATE\",\"timestamp\":\"1700000000123\"},\"host\":\"web01\",\"index\":\"security\",\
+            \"source\":\"fim\",\"sourcetype\":\"_json\",\"time\":1700000000.123}");
+
+        sink.settings.format = String::from("cef");
+        sink.settings.index = String::new();
+        let event = sink.get_event(&document);
+        assert_eq!(event["event"], json!(format::format_cef(&document)));
+        assert!(event.get("index").is_none());
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send_events() {
+        let dir = "test_splunk_send_events";
+        let (address, server) = start_server_with(vec![
+            (200, String::from("{\"text\":\"Success\",\"code\":0}")),
+            (503, String::from("{\"text\":\"Server is busy\",\"code\":9}")),
+            (200, String::from("{\"text\":\"Success\",\"code\":0}")),
+            (400, String::from("{\"text\":\"Invalid data format\",\"code\":6}")),
+            (403, String::from("{\"text\":\"Invalid token\",\"code\":4}"))
+        ]);
+        let sink = create_test_sink(&address, "", dir);
+        sink.setup().await.unwrap();
+        // Batch is sent when it is complete
+        sink.write(&create_test_document("1")).await.unwrap();
+        assert_eq!(get_status(&sink), (1, 0));
+        sink.write(&create_test_document("2")).await.unwrap();
+        assert_eq!(get_status(&sink), (0, 0));
+
+        // Failed events are kept and rejected ones moved aside
+        sink.write(&create_test_document("3")).await.unwrap();
+        assert!(sink.flush().await.is_err());
+        assert_eq!(get_status(&sink), (1, 0));
+        sink.flush().await.unwrap();
+        sink.write(&create_test_document("4")).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(get_status(&sink), (0, 0));
+        assert_eq!(fs::read_dir(format!("{}/splunk/rejected", dir)).unwrap().count(), 1);
+
+        // Events refused by a wrong token are kept, also after a restart
+        sink.write(&create_test_document("5")).await.unwrap();
+        assert!(sink.flush().await.is_err());
+        let sink = create_test_sink(&address, "", dir);
+        sink.setup().await.unwrap();
+        assert_eq!(get_status(&sink), (1, 0));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /services/collector/event "));
+        assert!(requests[0].contains("authorization: Splunk secret"));
+        let events: Vec<Value> = requests[0].split("\r\n\r\n").nth(1).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.iter().map(|event| event["event"]["id"].as_str().unwrap()).collect::<Vec<&str>>(), vec!["1", "2"]);
+        assert!(requests[2].ends_with("\"time\":1700000000.123}"));
+        assert!(requests[2].contains("\"id\":\"3\""));
+        assert!(requests[3].contains("\"id\":\"4\""));
+        assert!(requests[4].contains("\"id\":\"5\""));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_check_acks() {
+        let dir = "test_splunk_check_acks";
+        let channel = "0f4d9a5e-6a8b-4d3c-9d8e-2b1a7c6f5e4d";
+        let (address, server) = start_server_with(vec![
+            (200, String::from("{\"text\":\"Success\",\"code\":0,\"ackId\":7}")),
+            (200, String::from("{\"acks\":{\"7\":false}}")),
+            (200, String::from("{\"acks\":{\"7\":true}}"))
+        ]);
+        let sink = create_test_sink(&address, channel, dir);
+        sink.setup().await.unwrap();
+        sink.write(&create_test_document("1")).await.unwrap();
+        sink.write(&create_test_document("2")).await.unwrap();
+        // Events are kept until they are indexed without sending them again
+        assert_eq!(get_status(&sink), (2, 1));
+        sink.flush().await.unwrap();
+        assert_eq!(get_status(&sink), (2, 1));
+        sink.flush().await.unwrap();
+        assert_eq!(get_status(&sink), (0, 0));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].to_lowercase().contains(&format!("x-splunk-request-channel: {}", channel)));
+        assert!(requests[1].starts_with("POST /services/collector/ack "));
+        assert!(requests[1].ends_with("{\"acks\":[7]}"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright (C) 2021, Achiefs.
+
+// To handle files and folders
+use std::fs;
+// To manage paths
+use std::path::{Path, PathBuf};
+// To name spooled events in arrival order
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+// To share the spool of an output between its writes and flushes
+use std::sync::Mutex;
+// To handle JSON objects
+use serde_json::{json, Value};
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::event::{self, Delivery, Endpoint};
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+// Folder inside the spool of events refused by the endpoint
+const REJECTED_DIR: &str = "rejected";
+
+// ----------------------------------------------------------------------------
+
+// Directory of events waiting for delivery, one file per request named in arrival order
+pub struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    drop_newest: bool,
+    size: u64,
+    count: usize,
+    pub dropped: u64,
+    // Limits of each bulk request
+    pub batch_events: usize,
+    pub batch_bytes: u64
+}
+
+impl Spool {
+    pub fn new(dir: &str, max_size: u64, policy: &str) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Unable to create spool folder '{}', error: {}", dir, e))?;
+        let mut spool = Spool {
+            dir: PathBuf::from(dir),
+            max_size,
+            drop_newest: policy == "drop_newest",
+            size: 0,
+            count: 0,
+            dropped: 0,
+            batch_events: 1,
+            batch_bytes: 0
+        };
+        let pending = spool.pending();
+        spool.size = pending.iter().map(|path| get_size(path)).sum();
+        spool.count = pending.len();
+        Ok(spool)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get spooled events, oldest first
+    pub fn pending(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "json")).collect(),
+            Err(e) => {
+                error!("Unable to read spool folder '{}', error: {}", self.dir.display(), e);
+                Vec::new()
+            }
+        };
+        files.sort();
+        files
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Store an event making room with the configured policy, false if it was dropped
+    pub fn push(&mut self, url: &str, data: &Value) -> bool {
+        let line = json!({ "url": url, "data": data }).to_string();
+        let length = line.len() as u64;
+        if length > self.max_size {
+            self.dropped += 1;
+            warn!("Event bigger than the spool folder '{}' size, event dropped", self.dir.display());
+            return false;
+        }
+        if self.size + length > self.max_size {
+            let mut pending = self.pending().into_iter();
+            while ! self.drop_newest && self.size + length > self.max_size {
+                match pending.next() {
+                    Some(oldest) => {
+                        self.remove(&oldest);
+                        self.dropped += 1;
+                    },
+                    None => break
+                }
+            }
+            if self.size + length > self.max_size {
+                self.dropped += 1;
+                warn!("Spool folder '{}' is full, event dropped", self.dir.display());
+                return false;
+            }
+            warn!("Spool folder '{}' is full, oldest events dropped", self.dir.display());
+        }
+
+        let name = format!("{:020}-{:010}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_nanos(),
+            SEQUENCE.fetch_add(1, Ordering::Relaxed));
+        let tmp_file = self.dir.join(format!("{}.tmp", name));
+        match fs::write(&tmp_file, &line).and_then(|_| fs::rename(&tmp_file, self.dir.join(format!("{}.json", name)))) {
+            Ok(_) => {
+                self.size += length;
+                self.count += 1;
+                true
+            },
+            Err(e) => {
+                error!("Unable to store event in spool folder '{}', error: {}", self.dir.display(), e);
+                false
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn is_batch_ready(&self) -> bool {
+        self.count >= self.batch_events || self.size >= self.batch_bytes
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get the number of pending and dropped events
+    pub fn get_status(&self) -> (usize, u64) {
+        (self.count, self.dropped)
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Move an event that will never be delivered to the rejected folder, it is kept there
+    // for inspection and doesn't count in the spool size
+    pub fn reject(&mut self, path: &Path) {
+        let size = get_size(path);
+        let rejected_dir = self.dir.join(REJECTED_DIR);
+        let result = fs::create_dir_all(&rejected_dir)
+            .and_then(|_| fs::rename(path, rejected_dir.join(path.file_name().unwrap_or_default())));
+        match result {
+            Ok(_) => {
+                self.size = self.size.saturating_sub(size);
+                self.count = self.count.saturating_sub(1);
+            },
+            Err(e) => error!("Unable to move spooled event '{}' to '{}', error: {}", path.display(), rejected_dir.display(), e)
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn remove(&mut self, path: &Path) {
+        let size = get_size(path);
+        match fs::remove_file(path) {
+            Ok(_) => {
+                self.size = self.size.saturating_sub(size);
+                self.count = self.count.saturating_sub(1);
+            },
+            Err(e) => error!("Unable to remove spooled event '{}', error: {}", path.display(), e)
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn get_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+// ----------------------------------------------------------------------------
+
+// Read the endpoint URL and the document of a spooled event
+pub fn read(path: &Path) -> Option<(String, Value)> {
+    let mut value: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    let url = String::from(value["url"].as_str()?);
+    Some((url, value["data"].take()))
+}
+
+// ----------------------------------------------------------------------------
+
+// Open the spool folder of an output with the settings of the configuration, events are
+// sent without spool if it can't be used
+pub fn open(config: &config::Config, output: &config::Output) -> Option<Spool> {
+    let dir = Path::new(&config.spool_dir).join(&output.name);
+    match Spool::new(&dir.to_string_lossy(), config.spool_max_size, &config.spool_policy) {
+        Ok(mut spool) => {
+            (spool.batch_events, spool.batch_bytes) = config.get_batch(output);
+            info!("Events spool folder: {}, {} events pending", dir.display(), spool.pending().len());
+            Some(spool)
+        },
+        Err(e) => {
+            error!("{}, events will be sent without spool", e);
+            None
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Send spooled events in order with bulk requests, stopping at the first request with failed
+// events so they are retried later. Events are only removed once delivered, the ones rejected
+// by the endpoint are moved to the rejected folder as they would never be accepted
+pub async fn flush(spool: &Mutex<Option<Spool>>, endpoint: &Endpoint) -> usize {
+    let (pending, batch_events, batch_bytes) = match spool.lock().unwrap().as_ref() {
+        Some(spool) => (spool.pending(), spool.batch_events, spool.batch_bytes),
+        None => return 0
+    };
+    let mut pending = pending.into_iter().peekable();
+    let mut delivered = 0;
+    while pending.peek().is_some() {
+        // Documents of the batch with their spool file, all of them sent to the same address
+        let mut address = String::new();
+        let mut paths = Vec::new();
+        let mut documents = Vec::new();
+        let mut bytes = 0;
+        while let Some(path) = pending.peek() {
+            let size = get_size(path);
+            if paths.len() >= batch_events || (! paths.is_empty() && bytes + size > batch_bytes) {
+                break;
+            }
+            let document = read(path).and_then(|(url, data)| event::split_url(&url)
+                .map(|(url_address, index, id)| (url_address, (index, id, data))));
+            match document {
+                Some((url_address, _)) if ! paths.is_empty() && url_address != address => break,
+                Some((url_address, document)) => {
+                    address = url_address;
+                    paths.push(path.clone());
+                    documents.push(document);
+                    bytes += size;
+                },
+                None => {
+                    error!("Spooled event '{}' rejected, unable to read it", path.display());
+                    reject(spool, path);
+                }
+            }
+            pending.next();
+        }
+        if paths.is_empty() {
+            continue;
+        }
+
+        let mut failed = false;
+        let deliveries = endpoint.post_bulk(&address, &documents).await;
+        for (path, delivery) in paths.iter().zip(deliveries) {
+            match delivery {
+                Delivery::Delivered => {
+                    delivered += 1;
+                    remove(spool, path);
+                },
+                Delivery::Rejected(e) => {
+                    error!("Spooled event '{}' rejected by the endpoint, error: {}", path.display(), e);
+                    reject(spool, path);
+                },
+                Delivery::Failed(e) => {
+                    debug!("Spooled event '{}' kept to send it again, error: {}", path.display(), e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            break;
+        }
+    }
+    delivered
+}
+
+// ----------------------------------------------------------------------------
+
+fn remove(spool: &Mutex<Option<Spool>>, path: &Path) {
+    if let Some(spool) = spool.lock().unwrap().as_mut() {
+        spool.remove(path);
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn reject(spool: &Mutex<Option<Spool>>, path: &Path) {
+    if let Some(spool) = spool.lock().unwrap().as_mut() {
+        spool.reject(path);
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::start_server_with;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_push() {
+        let dir = "test_spool_push";
+        let mut spool = Spool::new(dir, 1000, "drop_oldest").unwrap();
+        assert!(spool.push("http://localhost/fim/_doc/1", &json!({"kind": "CREATE"})));
+        assert!(spool.push("http://localhost/fim/_doc/2", &json!({"kind": "WRITE"})));
+        let pending = spool.pending();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(read(&pending[0]), Some((String::from("http://localhost/fim/_doc/1"), json!({"kind": "CREATE"}))));
+        assert_eq!(read(&pending[1]).unwrap().1["kind"], "WRITE");
+
+        // Pending events are found again on restart
+        let mut spool = Spool::new(dir, 1000, "drop_oldest").unwrap();
+        assert_eq!(spool.size, pending.iter().map(|path| get_size(path)).sum::<u64>());
+        spool.remove(&pending[0]);
+        assert_eq!(spool.pending(), vec![pending[1].clone()]);
+        assert_eq!(spool.size, get_size(&pending[1]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_push_full() {
+        let data = json!({"kind": "CREATE"});
+        let length = json!({ "url": "u1", "data": data }).to_string().len() as u64;
+
+        let dir = "test_spool_drop_oldest";
+        let mut spool = Spool::new(dir, length * 2, "drop_oldest").unwrap();
+        assert!(spool.push("u1", &data));
+        assert!(spool.push("u2", &data));
+        assert!(spool.push("u3", &data));
+        let urls: Vec<String> = spool.pending().iter().map(|path| read(path).unwrap().0).collect();
+        assert_eq!(urls, vec!["u2", "u3"]);
+        assert_eq!(spool.dropped, 1);
+        fs::remove_dir_all(dir).unwrap();
+
+        let dir = "test_spool_drop_newest";
+        let mut spool = Spool::new(dir, length * 2, "drop_newest").unwrap();
+        assert!(spool.push("u1", &data));
+        assert!(spool.push("u2", &data));
+        assert!(!spool.push("u3", &data));
+        let urls: Vec<String> = spool.pending().iter().map(|path| read(path).unwrap().0).collect();
+        assert_eq!(urls, vec!["u1", "u2"]);
+        assert_eq!(spool.dropped, 1);
+        // Events bigger than the spool are never stored
+        let mut spool = Spool::new(dir, 1, "drop_oldest").unwrap();
+        assert!(!spool.push("u4", &data));
+        assert_eq!(spool.pending().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_flush() {
+        let dir = "test_spool_flush";
+        let items = |statuses: &[u16]| json!({
+            "errors": true,
+            "items": statuses.iter().map(|status| json!({ "index": { "status": status } })).collect::<Vec<Value>>()
+        }).to_string();
+        let (address, server) = start_server_with(vec![
+            (200, items(&[201, 400, 503])),
+            (200, items(&[201, 201])),
+            (401, String::new())
+        ]);
+        let spool = Mutex::new(Some(Spool::new(dir, 10000, "drop_oldest").unwrap()));
+        let endpoint = Endpoint::new(&address, "admin", "admin", false, crate::backoff::Policy {
+            max_attempts: 1, base_delay: 0, jitter: false, statuses: config::RETRY_STATUSES.to_vec()
+        });
+        spool.lock().unwrap().as_mut().unwrap().batch_events = 3;
+        spool.lock().unwrap().as_mut().unwrap().batch_bytes = 10000;
+        for id in 1..6 {
+            spool.lock().unwrap().as_mut().unwrap().push(&format!("{}/fim/_doc/{}", address, id), &json!({"id": id}));
+        }
+        assert!(spool.lock().unwrap().as_ref().unwrap().is_batch_ready());
+
+        // Delivered events are removed, rejected ones are moved aside, failed ones are kept and sending stops
+        assert_eq!(flush(&spool, &endpoint).await, 1);
+        let pending = spool.lock().unwrap().as_ref().unwrap().pending();
+        let urls: Vec<String> = pending.iter().map(|path| read(path).unwrap().0).collect();
+        assert_eq!(urls, vec![format!("{}/fim/_doc/3", address), format!("{}/fim/_doc/4", address),
+            format!("{}/fim/_doc/5", address)]);
+        let rejected: Vec<PathBuf> = fs::read_dir(Path::new(dir).join(REJECTED_DIR)).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(read(&rejected[0]).unwrap().0, format!("{}/fim/_doc/2", address));
+        assert!(spool.lock().unwrap().as_ref().unwrap().is_batch_ready());
+
+        // Each batch is sent in one bulk request
+        spool.lock().unwrap().as_mut().unwrap().batch_events = 2;
+        assert_eq!(flush(&spool, &endpoint).await, 2);
+
+        // Wrong credentials keep all events and make the endpoint unreachable
+        assert_eq!(flush(&spool, &endpoint).await, 0);
+        assert_eq!(spool.lock().unwrap().as_ref().unwrap().get_status(), (1, 0));
+        assert!(!endpoint.reachability.is_reachable());
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].starts_with("POST /_bulk "));
+        assert!(requests[0].ends_with("{\"index\":{\"_id\":\"1\",\"_index\":\"fim\"}}\n{\"id\":1}\n\
+            {\"index\":{\"_id\":\"2\",\"_index\":\"fim\"}}\n{\"id\":2}\n{\"index\":{\"_id\":\"3\",\"_index\":\"fim\"}}\n{\"id\":3}\n"));
+        assert!(requests[1].contains("{\"id\":3}\n"));
+        assert!(requests[1].contains("{\"id\":4}\n"));
+        assert_eq!(spool.lock().unwrap().as_ref().unwrap().pending().len(), 1);
+        assert!(!spool.lock().unwrap().as_ref().unwrap().is_batch_ready());
+
+        // Unreachable endpoint keeps all events
+        assert_eq!(flush(&spool, &endpoint).await, 0);
+        assert_eq!(spool.lock().unwrap().as_ref().unwrap().pending().len(), 1);
+        assert_eq!(flush(&Mutex::new(None), &endpoint).await, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+// Copyright (C) 2021, Achiefs.
+
+// To format syslog messages
+use serde_json::Value;
+// To send messages to the receiver
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::fs;
+// To use blocking sockets without stopping the runtime
+use tokio::task::spawn_blocking;
+// To encrypt connections to the receiver
+use native_tls::{Certificate, TlsConnector, TlsStream};
+// To log the program process
+use log::*;
+
+use crate::config;
+use crate::format;
+use crate::output::{Document, EventSink, SinkFuture};
+
+// Structured data identifier, number reserved for documentation by RFC 5612
+const SD_ID: &str = "fim@32473";
+const APP_NAME: &str = "fim";
+// Seconds to wait for the receiver on connections and writes
+const TIMEOUT: u64 = 10;
+
+// ----------------------------------------------------------------------------
+
+fn get_priority(facility: &str, kind: &str) -> usize {
+    let code = config::SYSLOG_FACILITIES.iter().position(|name| *name == facility).unwrap_or(16);
+    code * 8 + format::get_severity(kind) as usize
+}
+
+// ----------------------------------------------------------------------------
+
+// Header fields only allow printable characters without spaces, '-' means empty
+fn get_header_field(value: &str, max: usize) -> String {
+    let field: String = value.chars().filter(|c| c.is_ascii_graphic()).take(max).collect();
+    match field.is_empty() {
+        true => String::from("-"),
+        false => field
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the event fields as parameters, nested objects are joined with dots and null values skipped
+fn get_params(prefix: &str, value: &Value, params: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => for (key, value) in map {
+            let name = match prefix.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{}", prefix, key)
+            };
+            get_params(&name, value, params);
+        },
+        Value::Null => (),
+        other => params.push((String::from(prefix), format::get_text(other)))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+// ----------------------------------------------------------------------------
+
+// Get the structured data element with all the event data
+fn get_structured_data(document: &dyn Document, json: &Value) -> String {
+    let mut params = vec![(String::from("id"), document.get_id())];
+    get_params("", json, &mut params);
+    let params: String = params.iter()
+        .map(|(name, value)| {
+            let name: String = name.chars().filter(|c| c.is_ascii_graphic() && ! "=]\"".contains(*c)).take(32).collect();
+            format!(" {}=\"{}\"", name, escape_param(value))
+        }).collect();
+    format!("[{}{}]", SD_ID, params)
+}
+
+// ----------------------------------------------------------------------------
+
+// Format the event as RFC 5424 message, JSON events carry their data as structured data
+// and the rest the line of their format
+pub fn format_rfc5424(document: &dyn Document, facility: &str, format: &str) -> String {
+    let json = document.get_json();
+    let kind = json["kind"].as_str().unwrap_or_default();
+    format!("<{}>1 {} {} {} {} {} {}", get_priority(facility, kind), format::format_time(format::get_time(&json)),
+        get_header_field(json["hostname"].as_str().unwrap_or_default(), 255), APP_NAME,
+        get_header_field(&format::get_text(&json["pid"]), 128), get_header_field(kind, 32),
+        match format {
+            "json" => format!("{} {}", get_structured_data(document, &json), format::get_message(&json)),
+            _ => format!("- {}", format::get_line(document, format))
+        })
+}
+
+// ----------------------------------------------------------------------------
+
+// Format the event as RFC 3164 message for legacy receivers, the content is the line of the event
+pub fn format_rfc3164(document: &dyn Document, facility: &str, format: &str) -> String {
+    let json = document.get_json();
+    let time = format::get_time(&json);
+    let month = time.month().to_string();
+    format!("<{}>{} {:>2} {:02}:{:02}:{:02} {} {}[{}]: {}", get_priority(facility, json["kind"].as_str().unwrap_or_default()),
+        &month[..3], time.day(), time.hour(), time.minute(), time.second(),
+        get_header_field(json["hostname"].as_str().unwrap_or_default(), 255), APP_NAME,
+        format::get_text(&json["pid"]), format::get_line(document, format))
+}
+
+// ----------------------------------------------------------------------------
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixDatagram)
+}
+
+impl Connection {
+    // Stream messages are framed with octet counting
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        let closed = match self {
+            Connection::Tcp(stream) => is_closed(stream),
+            Connection::Tls(stream) => is_closed(stream.get_ref()),
+            _ => false
+        };
+        if closed {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed by the receiver"));
+        }
+        let frame = format!("{} {}", message.len(), message);
+        match self {
+            Connection::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            Connection::Tcp(stream) => stream.write_all(frame.as_bytes()),
+            Connection::Tls(stream) => stream.write_all(frame.as_bytes()),
+            #[cfg(unix)]
+            Connection::Unix(socket) => socket.send(message.as_bytes()).map(|_| ())
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Receivers don't send data, a readable stream was closed by them. The first write after
+// that succeeds without reaching the receiver so it is checked before each message
+fn is_closed(stream: &TcpStream) -> bool {
+    let mut buffer = [0; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let closed = match stream.peek(&mut buffer) {
+        Ok(read) => read == 0,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock
+    };
+    let _ = stream.set_nonblocking(false);
+    closed
+}
+
+// ----------------------------------------------------------------------------
+
+// Try every address of the receiver until one accepts the connection
+fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let mut result = Err(io::Error::new(io::ErrorKind::NotFound, format!("Address '{}' not resolved", address)));
+    for socket_address in address.to_socket_addrs()? {
+        result = TcpStream::connect_timeout(&socket_address, Duration::from_secs(TIMEOUT));
+        if let Ok(stream) = &result {
+            stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+            break;
+        }
+    }
+    result
+}
+
+// ----------------------------------------------------------------------------
+
+fn connect(settings: &config::Output) -> Result<Connection, String> {
+    let address = settings.address.as_str();
+    let error = |e: &dyn std::fmt::Display| format!("Unable to connect to '{}', error: {}", address, e);
+    match settings.protocol.as_str() {
+        "tcp" => connect_tcp(address).map(Connection::Tcp).map_err(|e| error(&e)),
+        "tls" => {
+            let mut builder = TlsConnector::builder();
+            if ! settings.ca.is_empty() {
+                let pem = fs::read(&settings.ca).map_err(|e| error(&e))?;
+                builder.add_root_certificate(Certificate::from_pem(&pem).map_err(|e| error(&e))?);
+            }
+            let connector = builder.danger_accept_invalid_certs(settings.insecure).build().map_err(|e| error(&e))?;
+            let stream = connect_tcp(address).map_err(|e| error(&e))?;
+            // Certificate is checked against the host of the address
+            let host = address.rsplit_once(':').map(|(host, _)| host).unwrap_or(address)
+                .trim_start_matches('[').trim_end_matches(']');
+            connector.connect(host, stream).map(|stream| Connection::Tls(Box::new(stream))).map_err(|e| error(&e))
+        },
+        #[cfg(unix)]
+        "unix" => {
+            let socket = UnixDatagram::unbound().map_err(|e| error(&e))?;
+            socket.connect(address).map_err(|e| error(&e))?;
+            Ok(Connection::Unix(socket))
+        },
+        #[cfg(not(unix))]
+        "unix" => Err(error(&"unix sockets not supported in this system")),
+        _ => {
+            let socket_address: SocketAddr = address.to_socket_addrs().map_err(|e| error(&e))?
+                .next().ok_or_else(|| error(&"address not resolved"))?;
+            let local = match socket_address.is_ipv6() {
+                true => "[::]:0",
+                false => "0.0.0.0:0"
+            };
+            let socket = UdpSocket::bind(local).map_err(|e| error(&e))?;
+            socket.connect(socket_address).map_err(|e| error(&e))?;
+            Ok(Connection::Udp(socket))
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Connections closed by the receiver are opened again once and the message is sent on the new one
+fn send(settings: &config::Output, connection: &Mutex<Option<Connection>>, message: &str) -> Result<(), String> {
+    let mut connection = connection.lock().unwrap();
+    let mut retried = false;
+    loop {
+        if connection.is_none() {
+            *connection = Some(connect(settings)?);
+        }
+        match connection.as_mut().unwrap().send(message) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                *connection = None;
+                if retried {
+                    return Err(format!("Unable to send message to '{}', error: {}", settings.address, e));
+                }
+                debug!("Syslog connection to '{}' lost, connecting again, error: {}", settings.address, e);
+                retried = true;
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Syslog receiver, messages are sent over UDP, TCP, TLS or to a local socket. Sockets are
+// blocking so they are used in the blocking threads of the runtime
+pub struct SyslogSink {
+    settings: Arc<config::Output>,
+    connection: Arc<Mutex<Option<Connection>>>
+}
+
+impl SyslogSink {
+    pub fn new(settings: &config::Output) -> Self {
+        SyslogSink {
+            settings: Arc::new(settings.clone()),
+            connection: Arc::new(Mutex::new(None))
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn format(&self, document: &dyn Document) -> String {
+        match self.settings.rfc {
+            3164 => format_rfc3164(document, &self.settings.facility, &self.settings.format),
+            _ => format_rfc5424(document, &self.settings.facility, &self.settings.format)
+        }
+    }
+}
+
+impl EventSink for SyslogSink {
+    fn setup(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            info!("Syslog receiver: {} over {}, RFC {}", self.settings.address, self.settings.protocol, self.settings.rfc);
+            let (settings, connection) = (self.settings.clone(), self.connection.clone());
+            spawn_blocking(move || {
+                *connection.lock().unwrap() = Some(connect(&settings)?);
+                Ok(())
+            }).await.map_err(|e| e.to_string())?
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn write<'a>(&'a self, document: &'a dyn Document) -> SinkFuture<'a> {
+        Box::pin(async {
+            let message = self.format(document);
+            let (settings, connection) = (self.settings.clone(), self.connection.clone());
+            spawn_blocking(move || send(&settings, &connection, &message)).await.map_err(|e| e.to_string())?
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle;
+    use crate::mock::TestDocument;
+    use serde_json::json;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use native_tls::{Identity, TlsAcceptor};
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_document() -> TestDocument {
+        TestDocument::new("1234", json!({
+            "timestamp": "1700000000123",
+            "hostname": "web 01",
+            "pid": 42,
+            "kind": "WRITE",
+            "file": "/etc/app]\"name\".conf",
+            "labels": ["etc", "app"],
+            "checksums": { "md5": "abc" },
+            "new_path": null
+        }))
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_settings(protocol: &str, address: &str) -> config::Output {
+        let mut settings = config::Output::new("syslog", "syslog");
+        settings.protocol = String::from(protocol);
+        settings.address = String::from(address);
+        settings.facility = String::from("local0");
+        settings
+    }
+
+    // ------------------------------------------------------------------------
+
+    fn create_test_sink(protocol: &str, address: &str) -> SyslogSink {
+        SyslogSink::new(&create_test_settings(protocol, address))
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_rfc5424() {
+        assert_eq!(format_rfc5424(&create_test_document(), "auth", "json"), "<37>1 2023-11-14T22:13:20.123Z web01 fim 42 WRITE \
+            [fim@32473 id=\"1234\" checksums.md5=\"abc\" file=\"/etc/app\\]\\\"name\\\".conf\" hostname=\"web 01\" \
+            kind=\"WRITE\" labels=\"etc,app\" pid=\"42\" timestamp=\"1700000000123\"] WRITE /etc/app]\"name\".conf");
+
+        let agent = TestDocument::new("1234", json!({ "kind": "WATCH_FAILED", "message": "Unable to watch /tmp" }));
+        let message = format_rfc5424(&agent, "local0", "json");
+        assert!(message.starts_with("<132>1 "));
+        assert!(message.contains(" - fim - WATCH_FAILED [fim@32473 id=\"1234\" kind=\"WATCH_FAILED\" message=\"Unable to watch /tmp\"] "));
+        assert!(message.ends_with("] Unable to watch /tmp"));
+
+        // Other formats are sent as message without structured data
+        let message = format_rfc5424(&agent, "local0", "cef");
+        assert!(message.ends_with(&format!("Z - fim - WATCH_FAILED - {}", format::format_cef(&agent))));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_format_rfc3164() {
+        let document = create_test_document();
+        assert_eq!(format_rfc3164(&document, "user", "json"),
+            format!("<13>Nov 14 22:13:20 web01 fim[42]: {}", document.format_json()));
+        assert_eq!(format_rfc3164(&document, "user", "leef"),
+            format!("<13>Nov 14 22:13:20 web01 fim[42]: {}", format::format_leef(&document)));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_priority() {
+        assert_eq!(get_priority("kern", lifecycle::WATCH_FAILED), 4);
+        assert_eq!(get_priority("local0", "CREATE"), 133);
+        assert_eq!(get_priority("local7", lifecycle::HEARTBEAT), 190);
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let sink = create_test_sink("udp", &receiver.local_addr().unwrap().to_string());
+        sink.setup().await.unwrap();
+        sink.write(&create_test_document()).await.unwrap();
+
+        let mut buffer = [0; 2048];
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(String::from_utf8_lossy(&buffer[..size]), sink.format(&create_test_document()));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // First connection is closed to check the sink connects again
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let sink = create_test_sink("tcp", &address);
+        sink.setup().await.unwrap();
+        thread::sleep(Duration::from_millis(100));
+        for _ in 0..3 {
+            sink.write(&create_test_document()).await.unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        drop(sink);
+
+        // Every message reaches the receiver through the new connection
+        let message = format_rfc5424(&create_test_document(), "local0", "json");
+        let frame = format!("{} {}", message.len(), message);
+        assert_eq!(server.join().unwrap(), frame.repeat(3));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn test_send_tls() {
+        let identity = Identity::from_pkcs8(&fs::read("test/unit/syslog.crt").unwrap(), &fs::read("test/unit/syslog.key").unwrap()).unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // Receiver certificate is not trusted by default
+            let (stream, _) = listener.accept().unwrap();
+            assert!(acceptor.accept(stream).is_err());
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(stream).unwrap();
+            let mut received = String::new();
+            let _ = stream.read_to_string(&mut received);
+            received
+        });
+
+        let mut settings = create_test_settings("tls", &format!("localhost:{}", port));
+        settings.rfc = 3164;
+        assert!(SyslogSink::new(&settings).setup().await.is_err());
+        settings.ca = String::from("test/unit/syslog.crt");
+        let sink = SyslogSink::new(&settings);
+        sink.write(&create_test_document()).await.unwrap();
+        drop(sink);
+
+        let message = format_rfc3164(&create_test_document(), "local0", "json");
+        assert_eq!(server.join().unwrap(), format!("{} {}", message.len(), message));
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_unix() {
+        let path = "test_syslog.sock";
+        let _ = fs::remove_file(path);
+        let receiver = UnixDatagram::bind(path).unwrap();
+        let sink = create_test_sink("unix", path);
+        sink.write(&create_test_document()).await.unwrap();
+
+        let mut buffer = [0; 2048];
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(String::from_utf8_lossy(&buffer[..size]), sink.format(&create_test_document()));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (C) 2021, Achiefs.
+
+// To implement Display method
+use std::fmt;
+// To manage paths
+use std::path::PathBuf;
+// To manage date and time
+use std::time::{SystemTime, UNIX_EPOCH};
+// To handle JSON objects
+use serde_json::{json, Value};
+// Event handling
+use notify::op::Op;
+
+use crate::baseline::{Baseline, Entry};
+use crate::config;
+use crate::metadata;
+
+// ----------------------------------------------------------------------------
+
+// File that doesn't match its approved state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub path: String,
+    // One of added, modified, attributes or missing
+    pub status: String,
+    // Aspects of an existing file that changed, content and attributes
+    pub changes: Vec<String>,
+    pub before: Option<Entry>,
+    pub after: Option<Entry>
+}
+
+impl Difference {
+    pub fn get_json(&self) -> Value {
+        json!({
+            "path": self.path.clone(),
+            "status": self.status.clone(),
+            "changes": self.changes.clone(),
+            "before": self.before,
+            "after": self.after
+        })
+    }
+
+    // ------------------------------------------------------------------------
+
+    // Get a short description of what changed
+    fn get_details(&self) -> String {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => {
+                let mut details = Vec::new();
+                if before.checksum != after.checksum {
+                    details.push(format!("checksum {} -> {}", short(&before.checksum), short(&after.checksum)));
+                }
+                if before.size != after.size {
+                    details.push(format!("size {} -> {}", before.size, after.size));
+                }
+                if before.mtime != after.mtime && details.is_empty() {
+                    details.push(format!("mtime {} -> {}", before.mtime, after.mtime));
+                }
+                if before.mode != after.mode {
+                    details.push(format!("mode {} -> {}", metadata::format_mode(before.mode), metadata::format_mode(after.mode)));
+                }
+                if before.uid != after.uid {
+                    details.push(format!("uid {} -> {}", before.uid, after.uid));
+                }
+                if before.gid != after.gid {
+                    details.push(format!("gid {} -> {}", before.gid, after.gid));
+                }
+                details.join(", ")
+            },
+            (None, Some(after)) => format!("checksum {}", short(&after.checksum)),
+            (Some(before), None) => format!("checksum {}", short(&before.checksum)),
+            (None, None) => String::new()
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Result of comparing monitored files with their approved state
+pub struct Report {
+    pub timestamp: String,
+    pub hostname: String,
+    pub nodename: String,
+    pub version: String,
+    pub baseline: String,
+    pub paths: Vec<String>,
+    pub checked: usize,
+    pub differences: Vec<Difference>
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    // ------------------------------------------------------------------------
+
+    pub fn get_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.clone(),
+            "hostname": self.hostname.clone(),
+            "node": self.nodename.clone(),
+            "version": self.version.clone(),
+            "baseline": self.baseline.clone(),
+            "paths": self.paths.clone(),
+            "checked": self.checked,
+            "result": if self.passed() { "pass" } else { "fail" },
+            "differences": self.differences.iter().map(|difference| difference.get_json()).collect::<Vec<Value>>()
+        })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "FIM verification report")?;
+        writeln!(f, "Approved baseline: {}", self.baseline)?;
+        writeln!(f, "Paths: {}", self.paths.join(", "))?;
+        writeln!(f, "Checked files: {}", self.checked)?;
+        for difference in &self.differences {
+            writeln!(f, "{:<11} {} ({})", difference.status.to_uppercase(), difference.path, difference.get_details())?;
+        }
+        match self.passed() {
+            true => writeln!(f, "Result: PASS"),
+            false => writeln!(f, "Result: FAIL ({} differences)", self.differences.len())
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn short(checksum: &str) -> &str {
+    &checksum[..checksum.len().min(16)]
+}
+
+// ----------------------------------------------------------------------------
+
+// Rehash the files of each monitor entry inside its root and compare them with the approved state
+pub fn verify(config: &config::Config, approved: &Baseline, roots: &[(usize, PathBuf)]) -> Report {
+    let mut checked = 0;
+    let mut differences = Vec::new();
+
+    for (index, root) in roots {
+        // Scan a copy so the approved state is kept untouched
+        let mut current = approved.clone();
+        let changes = current.scan_path(config, *index, root);
+        checked += current.count(config, *index, root);
+        for (operation, path, before) in changes {
+            let (status, change) = match operation {
+                Op::CREATE => ("added", None),
+                Op::WRITE => ("modified", Some("content")),
+                Op::REMOVE => { checked += 1; ("missing", None) },
+                _ => ("attributes", Some("attributes"))
+            };
+            // Content and attribute changes of the same file are reported together
+            if let Some(difference) = differences.last_mut().filter(|difference: &&mut Difference| difference.path == path) {
+                difference.changes.extend(change.map(String::from));
+                continue;
+            }
+            let after = current.entries.get(&path).cloned();
+            let changes = change.into_iter().map(String::from).collect();
+            differences.push(Difference { path, status: String::from(status), changes, before, after });
+        }
+    }
+
+    Report {
+        timestamp: format!("{:?}", SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis()),
+        hostname: gethostname::gethostname().into_string().unwrap(),
+        nodename: config.nodename.clone(),
+        version: String::from(config::VERSION),
+        baseline: config.approved_file.clone(),
+        paths: roots.iter().map(|(_, root)| String::from(root.to_str().unwrap())).collect(),
+        checked,
+        differences
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, env};
+    use std::path::Path;
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_verify() {
+        let root = "./test_verify";
+        fs::create_dir_all(format!("{}/subdir", root)).unwrap();
+        fs::write(format!("{}/file1", root), "This is a test!").unwrap();
+        fs::write(format!("{}/file2", root), "Another test!").unwrap();
+        fs::write(format!("{}/subdir/file3", root), "Last test!").unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        let yaml = format!("- path: {}", root);
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let roots = vec![(0, PathBuf::from(root))];
+
+        let mut approved = Baseline::new();
+        approved.scan(&config, 0);
+        let report = verify(&config, &approved, &roots);
+        assert!(report.passed());
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.get_json()["result"], "pass");
+
+        fs::write(format!("{}/file1", root), "Changed content").unwrap();
+        fs::remove_file(format!("{}/file2", root)).unwrap();
+        fs::write(format!("{}/subdir/file4", root), "New file").unwrap();
+        let report = verify(&config, &approved, &roots);
+        assert!(!report.passed());
+        assert_eq!(report.checked, 4);
+        let mut statuses: Vec<(String, String)> = report.differences.iter()
+            .map(|difference| (difference.status.clone(), difference.path.clone())).collect();
+        statuses.sort();
+        assert_eq!(statuses, vec![
+            (String::from("added"), String::from(Path::new(root).join("subdir/file4").to_str().unwrap())),
+            (String::from("missing"), String::from(Path::new(root).join("file2").to_str().unwrap())),
+            (String::from("modified"), String::from(Path::new(root).join("file1").to_str().unwrap()))
+        ]);
+        // Approved state is not modified
+        assert_eq!(approved.entries.len(), 3);
+
+        let json = report.get_json();
+        assert_eq!(json["result"], "fail");
+        assert_eq!(json["checked"], 4);
+        assert_eq!(json["differences"].as_array().unwrap().len(), 3);
+        let text = report.to_string();
+        assert!(text.contains("Checked files: 4"));
+        assert!(text.contains("MISSING"));
+        assert!(text.ends_with("Result: FAIL (3 differences)\n"));
+
+        // Only the selected path is verified
+        let report = verify(&config, &approved, &[(0, PathBuf::from(root).join("subdir"))]);
+        assert_eq!(report.differences.len(), 1);
+        assert_eq!(report.differences[0].status, String::from("added"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_content_and_attributes() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = "./test_verify_content_and_attributes";
+        fs::create_dir_all(root).unwrap();
+        let file = format!("{}/file1", root);
+        fs::write(&file, "This is a test!").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        let mut config = config::Config::new(env::consts::OS);
+        let yaml = format!("- path: {}", root);
+        config.monitor = serde_yaml::from_str(&yaml).unwrap();
+        let mut approved = Baseline::new();
+        approved.scan(&config, 0);
+
+        fs::write(&file, "Changed content").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        let report = verify(&config, &approved, &[(0, PathBuf::from(root))]);
+        assert_eq!(report.differences.len(), 1);
+        assert_eq!(report.differences[0].status, "modified");
+        assert_eq!(report.differences[0].changes, vec!["content", "attributes"]);
+        assert!(report.differences[0].get_details().contains("mode 0644 -> 0600"));
+        assert!(report.to_string().ends_with("Result: FAIL (1 differences)\n"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // ------------------------------------------------------------------------
+
+    #[test]
+    fn test_get_details() {
+        let before = Entry {
+            path: String::from("test"), size: 10, mode: 0o100644, uid: 0, gid: 0,
+            mtime: 1000, checksum: String::from("0123456789abcdef0123")
+        };
+        let mut after = before.clone();
+        after.mode = 0o100600;
+        after.uid = 1000;
+        let difference = Difference {
+            path: String::from("test"), status: String::from("attributes"), changes: vec![String::from("attributes")],
+            before: Some(before.clone()), after: Some(after)
+        };
+        assert_eq!(difference.get_details(), "mode 0644 -> 0600, uid 0 -> 1000");
+
+        let mut after = before.clone();
+        after.checksum = String::from("fedcba9876543210fedc");
+        after.size = 12;
+        let difference = Difference {
+            path: String::from("test"), status: String::from("modified"), changes: vec![String::from("content")],
+            before: Some(before.clone()), after: Some(after)
+        };
+        assert_eq!(difference.get_details(), "checksum 0123456789abcdef -> fedcba9876543210, size 10 -> 12");
+
+        let difference = Difference {
+            path: String::from("test"), status: String::from("missing"), changes: Vec::new(),
+            before: Some(before), after: None
+        };
+        assert_eq!(difference.get_details(), "checksum 0123456789abcdef");
+    }
+}